@@ -1,12 +1,17 @@
+use std::cmp::Ordering;
+
 use ggez::nalgebra::{
     Point2,
     Vector2,
 };
+use serde_derive::Deserialize;
 use specs::{
     prelude::*,
     Component,
 };
 
+use crate::grid::GridCell;
+
 #[derive(Clone, Copy, Debug, Component)]
 pub struct Transform {
     pub position: Point2<f32>,
@@ -20,7 +25,7 @@ impl Transform {
     }
 }
 
-#[derive(Clone, Copy, Debug, Component)]
+#[derive(Clone, Copy, Debug, Deserialize, Component)]
 pub enum Drawable {
     Tower,
     Enemy,
@@ -28,6 +33,7 @@ pub enum Drawable {
     Spawner,
     Base,
     Waypoint,
+    Beam,
 }
 
 #[derive(Clone, Debug, Component)]
@@ -35,6 +41,60 @@ pub struct Shooter {
     pub seconds_per_attack: f32,
     pub cooldown: f32,
     pub attack_radius: f32,
+    pub projectile_speed: f32,
+    pub aim: AimMode,
+    pub targeting: TargetingMode,
+}
+
+/// How a `Shooter` aims its projectiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum AimMode {
+    /// Fire straight at where the target is now.
+    Direct,
+    /// Fire at where the target will be when the projectile gets there, if it keeps moving the
+    /// same way.
+    Lead,
+    /// Fire projectiles that steer toward the target until they hit it.
+    Homing,
+}
+
+impl Default for AimMode {
+    fn default() -> Self {
+        AimMode::Direct
+    }
+}
+
+/// Which enemy in range a `Shooter` fires at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum TargetingMode {
+    /// The enemy furthest along its path.
+    First,
+    /// The enemy least far along its path.
+    Last,
+    Closest,
+    /// The enemy with the most HP.
+    Strongest,
+    /// The enemy with the least HP.
+    Weakest,
+}
+
+impl Default for TargetingMode {
+    fn default() -> Self {
+        TargetingMode::First
+    }
+}
+
+impl TargetingMode {
+    /// The mode after this one, for cycling through all of them.
+    pub fn next(self) -> Self {
+        match self {
+            TargetingMode::First => TargetingMode::Last,
+            TargetingMode::Last => TargetingMode::Closest,
+            TargetingMode::Closest => TargetingMode::Strongest,
+            TargetingMode::Strongest => TargetingMode::Weakest,
+            TargetingMode::Weakest => TargetingMode::First,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Component)]
@@ -70,30 +130,449 @@ impl Collider {
 #[derive(Clone, Debug, Component)]
 pub struct Projectile {}
 
+/// Removes an entity once it's been around for too long or moved too far.
+#[derive(Clone, Debug, Component)]
+pub struct Lifetime {
+    /// Seconds left before the entity is removed.
+    pub seconds: f32,
+    /// How much further the entity can move before it's removed.
+    pub distance: f32,
+}
+
+impl Lifetime {
+    pub fn new(seconds: f32, distance: f32) -> Self {
+        Self {
+            seconds,
+            distance,
+        }
+    }
+}
+
+/// Steers a projectile toward a target every frame. The projectile is removed if the target dies
+/// first.
+#[derive(Clone, Debug, Component)]
+pub struct Homing {
+    pub target: Entity,
+}
+
+/// Kinds of damage, which enemies can resist separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum DamageType {
+    /// The only kind of damage armor blocks.
+    Physical,
+    Magic,
+    Fire,
+    Cold,
+    Poison,
+}
+
+impl Default for DamageType {
+    fn default() -> Self {
+        DamageType::Physical
+    }
+}
+
+/// A tower the player built, and what they've spent on it.
+#[derive(Clone, Debug, Component)]
+pub struct Tower {
+    /// Name of the tower's `TowerDef`.
+    pub name: String,
+    /// Names of the upgrades bought, in order.
+    pub upgrades: Vec<String>,
+    /// Bits spent building and upgrading the tower.
+    pub spent: u32,
+    /// Grid cell the tower was built on.
+    pub cell: (u32, u32),
+    /// What the cell was before the tower was built, to put back when it's sold.
+    pub built_on: GridCell,
+}
+
+impl Tower {
+    pub fn new(name: &str, cost: u32, cell: (u32, u32), built_on: GridCell) -> Self {
+        Self {
+            name: name.to_owned(),
+            upgrades: Vec::new(),
+            spent: cost,
+            cell,
+            built_on,
+        }
+    }
+
+    /// Bits given back for selling the tower, as a fraction `refund` of what was spent on it.
+    pub fn sell_value(&self, refund: f32) -> u32 {
+        (self.spent as f32 * refund).round() as u32
+    }
+}
+
 #[derive(Clone, Debug, Component)]
 pub struct Attacker {
     pub damage: u32,
+    pub damage_type: DamageType,
+}
+
+/// Makes a `Shooter` hit its target instantly with a beam, instead of firing projectiles.
+#[derive(Clone, Debug, Deserialize, Component)]
+pub struct BeamAttack {
+    /// How many more enemies the beam jumps to after hitting the target.
+    #[serde(default)]
+    pub chain: u32,
+    /// How far the beam can jump from one enemy to the next.
+    #[serde(default)]
+    pub chain_radius: f32,
+}
+
+/// Where a beam drawn from the entity's `Transform` ends.
+#[derive(Clone, Debug, Component)]
+pub struct BeamSegment {
+    pub end: Point2<f32>,
+}
+
+/// Lets an attacker hit several targets before it's used up, instead of just one.
+#[derive(Clone, Debug, Component)]
+pub struct Pierce {
+    /// How many more targets it can hit.
+    pub remaining: u32,
+    /// Targets it has already hit, which it won't hit again.
+    pub hit: Vec<Entity>,
+}
+
+impl Pierce {
+    pub fn new(remaining: u32) -> Self {
+        Self {
+            remaining,
+            hit: Vec::new(),
+        }
+    }
+
+    /// Pierce that's never used up, for attackers that should keep fighting.
+    pub fn unlimited() -> Self {
+        Self::new(u32::MAX)
+    }
+}
+
+/// Makes an attack hurt every enemy within `radius` of where it hits, not just the one it hit.
+#[derive(Clone, Debug, Deserialize, Component)]
+pub struct AreaDamage {
+    pub radius: f32,
+    /// How much of the damage is lost at the edge of the radius, from 0 (none) to 1 (all of it).
+    /// Damage drops off linearly from the center.
+    pub falloff: f32,
+}
+
+impl AreaDamage {
+    /// Get how much of `damage` a target `distance` away from the impact takes.
+    pub fn damage_at(&self, damage: u32, distance: f32) -> u32 {
+        // Without a radius there's no edge to fall off toward.
+        if self.radius <= 0.0 {
+            return damage;
+        }
+        let falloff = self.falloff.clamp(0.0, 1.0) * (distance / self.radius).min(1.0);
+        (damage as f32 * (1.0 - falloff)).round() as u32
+    }
+}
+
+/// Flat damage blocked from every physical hit.
+#[derive(Clone, Debug, Component)]
+pub struct Armor {
+    pub value: u32,
+}
+
+/// How much less damage an entity takes of each type, from 1 (immune) down to -1 (double damage).
+#[derive(Clone, Debug, Default, Deserialize, Component)]
+#[serde(default)]
+pub struct Resistances {
+    pub physical: f32,
+    pub magic: f32,
+    pub fire: f32,
+    pub cold: f32,
+    pub poison: f32,
+}
+
+impl Resistances {
+    pub fn get(&self, damage_type: DamageType) -> f32 {
+        match damage_type {
+            DamageType::Physical => self.physical,
+            DamageType::Magic => self.magic,
+            DamageType::Fire => self.fire,
+            DamageType::Cold => self.cold,
+            DamageType::Poison => self.poison,
+        }
+    }
+}
+
+/// How many damage over time effects can be on an entity at once.
+pub const MAX_DAMAGE_OVER_TIME_STACKS: usize = 5;
+
+/// Kinds of timed effects attacks can put on their targets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum StatusKind {
+    /// Slows movement by `strength`, from 0 (not at all) to 1 (stopped). Only the strongest slow
+    /// applies.
+    Slow,
+    /// Deals `strength` poison damage per second, ignoring armor. Stacks up to
+    /// `MAX_DAMAGE_OVER_TIME_STACKS` times.
+    DamageOverTime,
+    /// Stops movement. Doesn't stack, but reapplying it can make it last longer.
+    Stun,
+    /// Removes `strength` armor. Stacks.
+    ArmorShred,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    /// Seconds the effect lasts, or has left once applied.
+    pub seconds: f32,
+    /// How strong the effect is. What this means depends on the kind.
+    #[serde(default)]
+    pub strength: f32,
+}
+
+/// Status effects an attacker puts on everything it hits.
+#[derive(Clone, Debug, Component)]
+pub struct InflictsEffects(pub Vec<StatusEffect>);
+
+/// Status effects currently on an entity.
+#[derive(Clone, Debug, Default, Component)]
+pub struct StatusEffects {
+    pub effects: Vec<StatusEffect>,
+    /// Damage over time that hasn't added up to a whole HP yet.
+    pub pending_damage: f32,
+}
+
+impl StatusEffects {
+    /// Add an effect, following the stacking rules for its kind.
+    pub fn apply(&mut self, effect: StatusEffect) {
+        match effect.kind {
+            StatusKind::Slow | StatusKind::Stun => {
+                // Reapplying an effect just as strong as one already on refreshes it instead.
+                let existing = self.effects.iter_mut().find(|existing| {
+                    existing.kind == effect.kind && (existing.strength - effect.strength).abs() < f32::EPSILON
+                });
+                match existing {
+                    Some(existing) => existing.seconds = existing.seconds.max(effect.seconds),
+                    None => self.effects.push(effect),
+                }
+            }
+            StatusKind::DamageOverTime => {
+                self.effects.push(effect);
+                // Past the limit, the stack closest to running out makes way for the new one.
+                if self.count(StatusKind::DamageOverTime) > MAX_DAMAGE_OVER_TIME_STACKS {
+                    let shortest = self.effects.iter()
+                        .enumerate()
+                        .filter(|(_, existing)| existing.kind == StatusKind::DamageOverTime)
+                        .min_by(|(_, a), (_, b)| a.seconds.partial_cmp(&b.seconds).unwrap_or(Ordering::Equal))
+                        .map(|(index, _)| index);
+                    if let Some(index) = shortest {
+                        self.effects.remove(index);
+                    }
+                }
+            }
+            StatusKind::ArmorShred => self.effects.push(effect),
+        }
+    }
+
+    /// How many effects of a kind are on the entity.
+    pub fn count(&self, kind: StatusKind) -> usize {
+        self.effects.iter().filter(|effect| effect.kind == kind).count()
+    }
+
+    /// What to scale the entity's speed by.
+    pub fn speed_multiplier(&self) -> f32 {
+        if self.count(StatusKind::Stun) > 0 {
+            return 0.0;
+        }
+        let slow = self.effects.iter()
+            .filter(|effect| effect.kind == StatusKind::Slow)
+            .fold(0.0, |slow: f32, effect| slow.max(effect.strength));
+        1.0 - slow.clamp(0.0, 1.0)
+    }
+
+    /// Total damage per second from every damage over time effect.
+    pub fn damage_per_second(&self) -> f32 {
+        self.effects.iter()
+            .filter(|effect| effect.kind == StatusKind::DamageOverTime)
+            .map(|effect| effect.strength)
+            .sum()
+    }
+
+    /// How much armor has been shredded.
+    pub fn armor_shred(&self) -> u32 {
+        self.effects.iter()
+            .filter(|effect| effect.kind == StatusKind::ArmorShred)
+            .map(|effect| effect.strength.max(0.0) as u32)
+            .sum()
+    }
 }
 
 #[derive(Clone, Debug, Component)]
 pub struct Health {
     pub current_hp: u32,
+    pub max_hp: u32,
+    /// HP restored per second, up to `max_hp`.
+    pub regen_per_second: f32,
+    /// Regeneration that hasn't added up to a whole HP yet.
+    pub pending_regen: f32,
+    /// Soaks up damage before HP does.
+    pub shield: Option<Shield>,
+}
+
+impl Health {
+    /// Full health, without regeneration or a shield.
+    pub fn new(max_hp: u32) -> Self {
+        Self {
+            current_hp: max_hp,
+            max_hp,
+            regen_per_second: 0.0,
+            pending_regen: 0.0,
+            shield: None,
+        }
+    }
+
+    /// How much of its HP the entity has left, from 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        if self.max_hp == 0 {
+            return 0.0;
+        }
+        self.current_hp as f32 / self.max_hp as f32
+    }
+
+    /// Take damage, using up the shield before any HP.
+    pub fn take_damage(&mut self, damage: u32) {
+        let damage = match &mut self.shield {
+            Some(shield) => shield.absorb(damage),
+            None => damage,
+        };
+        self.current_hp = self.current_hp.saturating_sub(damage);
+    }
+
+    /// Restore HP, up to `max_hp`.
+    pub fn heal(&mut self, hp: u32) {
+        self.current_hp = self.current_hp.saturating_add(hp).min(self.max_hp);
+    }
+
+    /// Regenerate HP and shield over `dt` seconds. The dead stay dead.
+    pub fn regenerate(&mut self, dt: f32) {
+        if self.current_hp == 0 {
+            return;
+        }
+
+        if self.current_hp < self.max_hp {
+            self.pending_regen += self.regen_per_second * dt;
+            let hp = self.pending_regen.floor();
+            self.pending_regen -= hp;
+            self.heal(hp as u32);
+        } else {
+            self.pending_regen = 0.0;
+        }
+
+        if let Some(shield) = &mut self.shield {
+            shield.regenerate(dt);
+        }
+    }
+}
+
+/// A layer of HP that takes damage first, and recharges after going a while without being hit.
+#[derive(Clone, Debug)]
+pub struct Shield {
+    pub current: u32,
+    pub max: u32,
+    /// Shield restored per second once it's recharging.
+    pub regen_per_second: f32,
+    /// Seconds without being hit before the shield starts recharging.
+    pub regen_delay: f32,
+    /// Seconds left before the shield starts recharging.
+    pub cooldown: f32,
+    /// Recharge that hasn't added up to a whole point yet.
+    pub pending_regen: f32,
+}
+
+impl Shield {
+    /// A fully charged shield.
+    pub fn new(max: u32, regen_per_second: f32, regen_delay: f32) -> Self {
+        Self {
+            current: max,
+            max,
+            regen_per_second,
+            regen_delay,
+            cooldown: 0.0,
+            pending_regen: 0.0,
+        }
+    }
+
+    /// Soak up as much of `damage` as possible, returning what gets through.
+    pub fn absorb(&mut self, damage: u32) -> u32 {
+        if damage > 0 {
+            self.cooldown = self.regen_delay;
+            self.pending_regen = 0.0;
+        }
+        let absorbed = damage.min(self.current);
+        self.current -= absorbed;
+        damage - absorbed
+    }
+
+    fn regenerate(&mut self, dt: f32) {
+        if self.cooldown > 0.0 {
+            self.cooldown -= dt;
+            return;
+        }
+        if self.current >= self.max {
+            self.pending_regen = 0.0;
+            return;
+        }
+        self.pending_regen += self.regen_per_second * dt;
+        let points = self.pending_regen.floor();
+        self.pending_regen -= points;
+        self.current = self.current.saturating_add(points as u32).min(self.max);
+    }
 }
 
 #[derive(Clone, Debug, Component)]
 pub struct Spawner {
     pub spawn_faction: Faction,
-    pub spawn_drawable: Drawable,
+    /// Name waves use to send enemies from this spawner.
+    pub name: String,
+    /// Name of the path spawned enemies walk.
+    pub path: String,
+    /// Id of the `Waypoint` on `path` spawned enemies head to first.
+    pub first_waypoint: u8,
+    /// Groups of enemies left to spawn. Each group spawns independently of the others.
+    pub groups: Vec<SpawnGroup>,
+}
+
+impl Spawner {
+    pub fn new(name: &str) -> Self {
+        Self {
+            spawn_faction: Faction::Enemy,
+            name: name.to_owned(),
+            path: String::new(),
+            first_waypoint: 0,
+            groups: Vec::new(),
+        }
+    }
+
+    /// Whether this spawner has finished spawning all of its groups.
+    ///
+    /// Finished groups are only removed on the frame after their last enemy spawns, once that
+    /// enemy actually exists in the world.
+    pub fn is_idle(&self) -> bool {
+        self.groups.is_empty()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpawnGroup {
+    /// Name of the `EnemyArchetype` to spawn.
+    pub enemy: String,
     pub count: u32,
     pub seconds_to_spawn: f32,
     pub cooldown: f32,
 }
 
-impl Spawner {
-    pub fn default() -> Self {
+impl Default for SpawnGroup {
+    fn default() -> Self {
         Self {
-            spawn_faction: Faction::Enemy,
-            spawn_drawable: Drawable::Enemy,
+            enemy: "basic".to_owned(),
             count: 5,
             seconds_to_spawn: 3.0,
             cooldown: 0.0,
@@ -107,9 +586,181 @@ pub struct Base {}
 #[derive(Clone, Debug, Component)]
 pub struct Enemy {
     pub current_waypoint: Entity,
+    pub speed: f32,
+    /// Distance left to the end of the route, so towers can tell which enemies are furthest along.
+    pub distance_left: f32,
+    /// Bits awarded for killing this enemy.
+    pub bounty: u32,
+    /// Seconds until the enemy can attack again, once it's at its goal.
+    pub attack_cooldown: f32,
 }
 
 #[derive(Clone, Debug, Component)]
 pub struct Waypoint {
+    /// Enemies walk through the waypoints on a path in order of id.
     pub id: u8,
+    /// Name of the path this waypoint is on.
+    pub path: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn effect(kind: StatusKind, seconds: f32, strength: f32) -> StatusEffect {
+        StatusEffect {
+            kind,
+            seconds,
+            strength,
+        }
+    }
+
+    #[test]
+    fn strongest_slow_wins() {
+        let mut status = StatusEffects::default();
+        status.apply(effect(StatusKind::Slow, 2.0, 0.25));
+        status.apply(effect(StatusKind::Slow, 1.0, 0.5));
+        assert_eq!(status.count(StatusKind::Slow), 2);
+        assert_eq!(status.speed_multiplier(), 0.5);
+
+        // Slows past stopped still only stop.
+        status.apply(effect(StatusKind::Slow, 1.0, 2.0));
+        assert_eq!(status.speed_multiplier(), 0.0);
+    }
+
+    #[test]
+    fn stuns_stop_movement() {
+        let mut status = StatusEffects::default();
+        status.apply(effect(StatusKind::Slow, 1.0, 0.25));
+        status.apply(effect(StatusKind::Stun, 1.0, 0.0));
+        assert_eq!(status.speed_multiplier(), 0.0);
+    }
+
+    #[test]
+    fn equal_strength_effects_refresh() {
+        let mut status = StatusEffects::default();
+        status.apply(effect(StatusKind::Slow, 1.0, 0.5));
+        status.apply(effect(StatusKind::Slow, 3.0, 0.5));
+        assert_eq!(status.count(StatusKind::Slow), 1);
+        assert_eq!(status.effects[0].seconds, 3.0);
+
+        // Refreshing never shortens an effect.
+        status.apply(effect(StatusKind::Slow, 2.0, 0.5));
+        assert_eq!(status.effects[0].seconds, 3.0);
+
+        status.apply(effect(StatusKind::Stun, 1.0, 0.0));
+        status.apply(effect(StatusKind::Stun, 2.0, 0.0));
+        assert_eq!(status.count(StatusKind::Stun), 1);
+    }
+
+    #[test]
+    fn damage_over_time_stacks_up_to_the_cap() {
+        let mut status = StatusEffects::default();
+        for i in 0..MAX_DAMAGE_OVER_TIME_STACKS {
+            status.apply(effect(StatusKind::DamageOverTime, 2.0 + i as f32, 1.0));
+        }
+        assert_eq!(status.damage_per_second(), MAX_DAMAGE_OVER_TIME_STACKS as f32);
+
+        // The stack closest to running out makes way for the new one.
+        status.apply(effect(StatusKind::DamageOverTime, 10.0, 3.0));
+        assert_eq!(status.count(StatusKind::DamageOverTime), MAX_DAMAGE_OVER_TIME_STACKS);
+        assert_eq!(status.damage_per_second(), MAX_DAMAGE_OVER_TIME_STACKS as f32 + 2.0);
+        assert!(status.effects.iter().all(|effect| effect.seconds > 2.0));
+    }
+
+    #[test]
+    fn armor_shred_adds_up() {
+        let mut status = StatusEffects::default();
+        assert_eq!(status.armor_shred(), 0);
+        status.apply(effect(StatusKind::ArmorShred, 1.0, 2.0));
+        status.apply(effect(StatusKind::ArmorShred, 1.0, 2.0));
+        status.apply(effect(StatusKind::ArmorShred, 1.0, 1.0));
+        assert_eq!(status.armor_shred(), 5);
+    }
+
+    fn shielded(hp: u32, shield: u32) -> Health {
+        let mut health = Health::new(hp);
+        health.shield = Some(Shield::new(shield, 2.0, 1.0));
+        health
+    }
+
+    #[test]
+    fn regen_carries_over_fractions() {
+        let mut health = Health::new(10);
+        health.regen_per_second = 1.5;
+        health.current_hp = 5;
+        health.regenerate(0.5);
+        assert_eq!(health.current_hp, 5);
+        health.regenerate(0.5);
+        assert_eq!(health.current_hp, 6);
+        assert_eq!(health.pending_regen, 0.5);
+    }
+
+    #[test]
+    fn regen_stops_at_max_hp() {
+        let mut health = Health::new(10);
+        health.regen_per_second = 5.0;
+        health.current_hp = 9;
+        health.regenerate(1.0);
+        assert_eq!(health.current_hp, 10);
+        health.regenerate(1.0);
+        assert_eq!(health.current_hp, 10);
+        assert_eq!(health.pending_regen, 0.0);
+    }
+
+    #[test]
+    fn the_dead_stay_dead() {
+        let mut health = shielded(10, 5);
+        health.regen_per_second = 5.0;
+        health.take_damage(20);
+        assert_eq!(health.current_hp, 0);
+        for _ in 0..10 {
+            health.regenerate(1.0);
+        }
+        assert_eq!(health.current_hp, 0);
+        assert_eq!(health.shield.unwrap().current, 0);
+    }
+
+    #[test]
+    fn shields_take_damage_first() {
+        let mut health = shielded(10, 5);
+        health.take_damage(3);
+        assert_eq!((health.current_hp, health.shield.as_ref().unwrap().current), (10, 2));
+        health.take_damage(4);
+        assert_eq!((health.current_hp, health.shield.as_ref().unwrap().current), (8, 0));
+    }
+
+    #[test]
+    fn shields_recharge_after_the_delay() {
+        let mut shield = Shield::new(10, 2.0, 1.0);
+        shield.absorb(6);
+        shield.regenerate(0.5);
+        assert_eq!(shield.current, 4);
+        // Being hit again restarts the delay.
+        shield.absorb(1);
+        shield.regenerate(0.75);
+        assert_eq!(shield.current, 3);
+        shield.regenerate(0.25);
+        shield.regenerate(1.0);
+        assert_eq!(shield.current, 5);
+    }
+
+    #[test]
+    fn shields_recharge_up_to_max() {
+        let mut shield = Shield::new(10, 2.0, 0.0);
+        shield.absorb(1);
+        shield.regenerate(5.0);
+        assert_eq!(shield.current, 10);
+        assert_eq!(shield.pending_regen, 0.0);
+    }
+
+    #[test]
+    fn no_damage_doesnt_stop_recharging() {
+        let mut shield = Shield::new(10, 2.0, 1.0);
+        shield.absorb(4);
+        shield.regenerate(1.0);
+        shield.absorb(0);
+        shield.regenerate(1.0);
+        assert_eq!(shield.current, 8);
+    }
 }
@@ -0,0 +1,100 @@
+//! How much damage a hit actually deals once the target's defenses are taken into account.
+
+use crate::components::{DamageType, Resistances};
+
+/// Hits that get through at all always deal at least this much damage, so armor can't make an
+/// enemy unkillable.
+pub const MIN_DAMAGE: u32 = 1;
+
+/// The lowest a resistance can go. At -1, hits of that type deal double damage.
+pub const MIN_RESISTANCE: f32 = -1.0;
+
+/// Scale damage by how resistant the target is to its type.
+pub fn resist(damage: f32, damage_type: DamageType, resistances: Option<&Resistances>) -> f32 {
+    let resistance = resistances.map_or(0.0, |resistances| resistances.get(damage_type));
+    damage * (1.0 - resistance.clamp(MIN_RESISTANCE, 1.0))
+}
+
+/// Work out the final damage of a hit.
+///
+/// Resistances scale the damage first, then armor is taken off of physical hits. Hits that were
+/// meant to do damage deal at least `MIN_DAMAGE`, unless the target is fully resistant to them.
+pub fn final_damage(damage: u32, damage_type: DamageType, armor: u32, resistances: Option<&Resistances>) -> u32 {
+    let resisted = resist(damage as f32, damage_type, resistances);
+    if damage == 0 || resisted <= 0.0 {
+        return 0;
+    }
+
+    let blocked = match damage_type {
+        DamageType::Physical => armor,
+        _ => 0,
+    };
+    (resisted.round() as u32).saturating_sub(blocked).max(MIN_DAMAGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resistances(magic: f32) -> Resistances {
+        Resistances {
+            magic,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn undefended_targets_take_full_damage() {
+        assert_eq!(final_damage(5, DamageType::Physical, 0, None), 5);
+        assert_eq!(final_damage(5, DamageType::Magic, 0, Some(&Resistances::default())), 5);
+    }
+
+    #[test]
+    fn armor_only_blocks_physical_damage() {
+        assert_eq!(final_damage(5, DamageType::Physical, 2, None), 3);
+        assert_eq!(final_damage(5, DamageType::Magic, 2, None), 5);
+    }
+
+    #[test]
+    fn hits_deal_at_least_min_damage() {
+        assert_eq!(final_damage(2, DamageType::Physical, 10, None), MIN_DAMAGE);
+        assert_eq!(final_damage(1, DamageType::Magic, 0, Some(&resistances(0.9))), MIN_DAMAGE);
+    }
+
+    #[test]
+    fn no_damage_stays_no_damage() {
+        assert_eq!(final_damage(0, DamageType::Physical, 0, None), 0);
+        assert_eq!(final_damage(0, DamageType::Magic, 0, Some(&resistances(-1.0))), 0);
+    }
+
+    #[test]
+    fn immune_targets_take_no_damage() {
+        assert_eq!(final_damage(5, DamageType::Magic, 0, Some(&resistances(1.0))), 0);
+        assert_eq!(final_damage(5, DamageType::Magic, 0, Some(&resistances(2.0))), 0);
+    }
+
+    #[test]
+    fn resistances_scale_damage() {
+        assert_eq!(final_damage(4, DamageType::Magic, 0, Some(&resistances(0.5))), 2);
+        assert_eq!(final_damage(4, DamageType::Magic, 0, Some(&resistances(-0.5))), 6);
+        // Weaknesses are capped at double damage.
+        assert_eq!(final_damage(4, DamageType::Magic, 0, Some(&resistances(-3.0))), 8);
+        // Other damage types aren't affected.
+        assert_eq!(final_damage(4, DamageType::Fire, 0, Some(&resistances(0.5))), 4);
+    }
+
+    #[test]
+    fn resistances_apply_before_armor() {
+        let resistances = Resistances {
+            physical: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(final_damage(10, DamageType::Physical, 2, Some(&resistances)), 3);
+    }
+
+    #[test]
+    fn resist_scales_fractional_damage() {
+        assert_eq!(resist(0.5, DamageType::Magic, Some(&resistances(0.5))), 0.25);
+        assert_eq!(resist(0.5, DamageType::Magic, None), 0.5);
+    }
+}
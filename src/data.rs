@@ -0,0 +1,277 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+
+use crate::components::*;
+
+const TOWERS_PATH: &str = "assets/towers.toml";
+const ENEMIES_PATH: &str = "assets/enemies.toml";
+
+#[derive(Debug)]
+pub enum DataError {
+    Io(String, io::Error),
+    Parse(String, toml::de::Error),
+    /// The file parsed, but something in it doesn't make sense.
+    Invalid(String, String),
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataError::Io(path, e) => write!(f, "Could not read \"{}\": {}", path, e),
+            DataError::Parse(path, e) => write!(f, "Could not parse \"{}\": {}", path, e),
+            DataError::Invalid(path, reason) => write!(f, "Invalid data in \"{}\": {}", path, reason),
+        }
+    }
+}
+
+impl Error for DataError {}
+
+/// Read and deserialize a TOML data file.
+pub fn load_toml<T: DeserializeOwned>(path: &str) -> Result<T, DataError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| DataError::Io(path.to_owned(), e))?;
+    toml::from_str(&contents)
+        .map_err(|e| DataError::Parse(path.to_owned(), e))
+}
+
+/// Stats for a type of tower the player can build.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TowerDef {
+    pub name: String,
+    pub cost: u32,
+    pub seconds_per_attack: f32,
+    pub attack_radius: f32,
+    /// Only optional for beam towers, which don't fire projectiles.
+    #[serde(default)]
+    pub projectile_speed: f32,
+    pub damage: u32,
+    #[serde(default)]
+    pub damage_type: DamageType,
+    #[serde(default)]
+    pub aim: AimMode,
+    /// Makes projectiles damage every enemy near where they hit.
+    #[serde(default)]
+    pub area_damage: Option<AreaDamage>,
+    /// How many enemies each projectile can hit before it's used up. Defaults to 1.
+    #[serde(default)]
+    pub pierce: Option<u32>,
+    /// Makes the tower hit instantly with a beam instead of firing projectiles.
+    #[serde(default)]
+    pub beam: Option<BeamAttack>,
+    /// Status effects put on every enemy the tower hits.
+    #[serde(default)]
+    pub effects: Vec<StatusEffect>,
+    /// Which enemy in range the tower shoots at when first built.
+    #[serde(default)]
+    pub targeting: TargetingMode,
+    pub drawable: Drawable,
+    /// Upgrades that can be bought for the tower once it's built.
+    #[serde(rename = "upgrade", default)]
+    pub upgrades: Vec<UpgradeDef>,
+}
+
+impl TowerDef {
+    /// Get the upgrades that can be bought next for a tower that already has `bought`, in order.
+    pub fn upgrade_options(&self, bought: &[String]) -> Vec<&UpgradeDef> {
+        let last = bought.last();
+        self.upgrades.iter()
+            .filter(|upgrade| upgrade.requires.as_ref() == last)
+            .collect()
+    }
+}
+
+/// An upgrade for a built tower. Numbers are added to the tower's stats, while anything else
+/// replaces what the tower had.
+///
+/// Upgrades form a tree: each one requires the upgrade bought right before it, so several
+/// upgrades requiring the same one are a choice between branches.
+#[derive(Clone, Debug, Deserialize)]
+pub struct UpgradeDef {
+    pub name: String,
+    pub cost: u32,
+    /// Upgrade that has to be bought right before this one. First tier upgrades have none.
+    #[serde(default)]
+    pub requires: Option<String>,
+    #[serde(default)]
+    pub damage: u32,
+    #[serde(default)]
+    pub attack_radius: f32,
+    /// Negative to make the tower attack faster.
+    #[serde(default)]
+    pub seconds_per_attack: f32,
+    #[serde(default)]
+    pub projectile_speed: f32,
+    #[serde(default)]
+    pub aim: Option<AimMode>,
+    #[serde(default)]
+    pub area_damage: Option<AreaDamage>,
+    #[serde(default)]
+    pub pierce: Option<u32>,
+    #[serde(default)]
+    pub beam: Option<BeamAttack>,
+    /// Status effects put on every enemy hit, on top of the ones the tower already has.
+    #[serde(default)]
+    pub effects: Vec<StatusEffect>,
+}
+
+/// All tower types, in the order they're offered to the player.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct TowerCatalog {
+    #[serde(rename = "tower", default)]
+    pub towers: Vec<TowerDef>,
+}
+
+impl TowerCatalog {
+    pub fn load() -> Result<Self, DataError> {
+        let catalog: Self = load_toml(TOWERS_PATH)?;
+        catalog.validate()
+            .map_err(|reason| DataError::Invalid(TOWERS_PATH.to_owned(), reason))?;
+        Ok(catalog)
+    }
+
+    /// Check for stats that parse fine but would make a tower misbehave.
+    fn validate(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for tower in &self.towers {
+            // Towers are looked up by name, so later ones with the same name could never be used.
+            if !names.insert(&tower.name) {
+                return Err(format!("More than one tower is named \"{}\"", tower.name));
+            }
+            // Projectiles that don't move would never hit anything.
+            if tower.beam.is_none() && tower.projectile_speed <= 0.0 {
+                return Err(format!("Tower \"{}\" needs a projectile_speed above 0, or a beam", tower.name));
+            }
+            let area_damages = tower.area_damage.iter()
+                .chain(tower.upgrades.iter().filter_map(|upgrade| upgrade.area_damage.as_ref()));
+            for area_damage in area_damages {
+                if area_damage.radius <= 0.0 {
+                    return Err(format!("Tower \"{}\" has area_damage with a radius that isn't above 0", tower.name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TowerDef> {
+        self.towers.iter().find(|tower| tower.name == name)
+    }
+}
+
+/// Stats for a type of enemy that spawners can create.
+#[derive(Clone, Debug, Deserialize)]
+pub struct EnemyArchetype {
+    pub name: String,
+    pub speed: f32,
+    pub hp: u32,
+    /// HP restored per second.
+    #[serde(default)]
+    pub regen_per_second: f32,
+    /// A rechargeable shield that takes damage before HP does.
+    #[serde(default)]
+    pub shield: Option<ShieldDef>,
+    pub width: f32,
+    pub height: f32,
+    pub damage: u32,
+    /// Damage blocked from every physical hit.
+    #[serde(default)]
+    pub armor: u32,
+    /// How much less damage the enemy takes of each type.
+    #[serde(default)]
+    pub resistances: Resistances,
+    pub bounty: u32,
+    /// Melee enemies keep attacking the base, instead of being used up by their first hit.
+    #[serde(default)]
+    pub melee: bool,
+    pub drawable: Drawable,
+}
+
+/// Stats for an enemy's shield.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ShieldDef {
+    pub hp: u32,
+    #[serde(default)]
+    pub regen_per_second: f32,
+    /// Seconds without being hit before the shield starts recharging.
+    #[serde(default)]
+    pub regen_delay: f32,
+}
+
+/// All enemy archetypes, looked up by name.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct EnemyCatalog {
+    #[serde(rename = "enemy", default)]
+    pub enemies: Vec<EnemyArchetype>,
+}
+
+impl EnemyCatalog {
+    pub fn load() -> Result<Self, DataError> {
+        let catalog: Self = load_toml(ENEMIES_PATH)?;
+        catalog.validate()
+            .map_err(|reason| DataError::Invalid(ENEMIES_PATH.to_owned(), reason))?;
+        Ok(catalog)
+    }
+
+    /// Check for stats that parse fine but would make an enemy misbehave.
+    fn validate(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for enemy in &self.enemies {
+            if !names.insert(&enemy.name) {
+                return Err(format!("More than one enemy is named \"{}\"", enemy.name));
+            }
+            // Enemies without HP count as dead already, so they'd never be removed.
+            if enemy.hp == 0 {
+                return Err(format!("Enemy \"{}\" needs hp above 0", enemy.name));
+            }
+            if enemy.speed <= 0.0 {
+                return Err(format!("Enemy \"{}\" needs a speed above 0", enemy.name));
+            }
+            if enemy.width <= 0.0 || enemy.height <= 0.0 {
+                return Err(format!("Enemy \"{}\" needs a width and height above 0", enemy.name));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&EnemyArchetype> {
+        self.enemies.iter().find(|enemy| enemy.name == name)
+    }
+}
+
+/// The waves of enemies a level sends at the player, in order.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct WaveSchedule {
+    /// Seconds before the first wave starts.
+    #[serde(default)]
+    pub first_countdown: f32,
+    /// Seconds between a wave finishing spawning and the next wave starting.
+    pub countdown: f32,
+    #[serde(rename = "wave", default)]
+    pub waves: Vec<WaveDef>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WaveDef {
+    #[serde(rename = "group", default)]
+    pub groups: Vec<WaveGroupDef>,
+}
+
+/// A group of enemies of one archetype sent from a spawner during a wave.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WaveGroupDef {
+    /// Name of the spawner to send this group from. Sent from every spawner when omitted.
+    #[serde(default)]
+    pub spawner: Option<String>,
+    pub enemy: String,
+    pub count: u32,
+    /// Seconds between each enemy in the group.
+    pub interval: f32,
+    /// Seconds after the wave starts before the first enemy spawns.
+    #[serde(default)]
+    pub delay: f32,
+}
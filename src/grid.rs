@@ -11,6 +11,10 @@ pub struct Grid {
     pub height: u32,
     pub cell_size: f32,
     pub grid: Vec<GridCell>,
+    /// Cells changed by `set_cell` that haven't been taken yet.
+    changes: Vec<(u32, u32)>,
+    /// Counts every change to a cell, so anything cached from the grid can tell it's stale.
+    revision: u64,
 }
 
 impl Grid {
@@ -20,22 +24,68 @@ impl Grid {
             height,
             cell_size,
             grid: vec![GridCell::Buildable; (width * height) as usize],
+            changes: Vec::new(),
+            revision: 0,
         }
     }
 
+    pub fn in_bounds(&self, x: u32, y: u32) -> bool {
+        x < self.width && y < self.height
+    }
+
     pub fn get_cell(&self, x: u32, y: u32) -> Option<GridCell> {
+        if !self.in_bounds(x, y) {
+            return None;
+        }
         self.grid.get((y * self.width + x) as usize).cloned()
     }
 
     pub fn set_cell(&mut self, x: u32, y: u32, value: GridCell) -> bool {
+        if !self.in_bounds(x, y) {
+            return false;
+        }
         if let Some(cell) = self.grid.get_mut((y * self.width + x) as usize) {
-            *cell = value;
+            if *cell != value {
+                *cell = value;
+                self.changes.push((x, y));
+                self.revision += 1;
+            }
             true
         } else {
             false
         }
     }
 
+    /// Get how many times a cell has changed since the grid was made.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Take the cells that have changed since this was last called, so anything built from the
+    /// grid can catch up.
+    pub fn take_changes(&mut self) -> Vec<(u32, u32)> {
+        std::mem::take(&mut self.changes)
+    }
+
+    /// Get the cell containing a point in world coordinates, if it's on the grid.
+    pub fn cell_at(&self, x: f32, y: f32) -> Option<(u32, u32)> {
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let (cell_x, cell_y) = ((x / self.cell_size) as u32, (y / self.cell_size) as u32);
+        if self.in_bounds(cell_x, cell_y) {
+            Some((cell_x, cell_y))
+        } else {
+            None
+        }
+    }
+
+    /// Get the center of a cell in world coordinates.
+    pub fn cell_center(&self, x: u32, y: u32) -> (f32, f32) {
+        ((x as f32 * self.cell_size) + self.cell_size / 2.0,
+         (y as f32 * self.cell_size) + self.cell_size / 2.0)
+    }
+
     pub fn is_walkable(&self, x: u32, y: u32) -> bool {
         self.get_cell(x, y) == Some(GridCell::Walkable)
     }
@@ -47,4 +97,9 @@ impl Grid {
     pub fn is_occupied(&self, x: u32, y: u32) -> bool {
         self.get_cell(x, y) == Some(GridCell::Occupied)
     }
+
+    /// Whether nothing has been placed on the cell.
+    pub fn is_open(&self, x: u32, y: u32) -> bool {
+        self.is_walkable(x, y) || self.is_buildable(x, y)
+    }
 }
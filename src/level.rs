@@ -1,15 +1,37 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::error::Error;
 use std::ffi::OsStr;
+use std::fmt;
+use std::path::Path;
 
-use tiled::PropertyValue;
+use tiled::{Object, Properties, PropertyValue, TiledError};
 use specs::prelude::*;
 use walkdir::{DirEntry, WalkDir};
 
 use crate::grid::*;
 use crate::components::*;
+use crate::data::*;
+use crate::pathfinding::*;
 use crate::resources::*;
 
 const LEVEL_DIR: &str = "assets/levels";
 
+// Names of the Tiled layers the loader understands.
+/// Tile layer painting which cells are buildable and walkable. Required.
+const GRID_LAYER: &str = "Grid";
+/// Tile layers that are only art. Ignored by the loader.
+const DECORATION_LAYER: &str = "Decoration";
+/// Object group with the level's bases, spawners, and waypoints. Required.
+const ENTITIES_GROUP: &str = "Entities";
+/// Optional object groups for keeping waypoints and spawners apart from other entities. Their
+/// objects are treated the same as those in the Entities group.
+const PATHS_GROUP: &str = "Paths";
+const WAVES_GROUP: &str = "Waves";
+
+/// Spawner properties that configure the group of enemies it sends on its own.
+const SPAWN_GROUP_PROPERTIES: &[&str] = &["enemy", "count", "seconds_to_spawn", "start_delay"];
+
 pub fn find_levels() -> Vec<String> {
     fn has_tmx_extension(entry: &DirEntry) -> bool {
         entry.path().extension() == Some(OsStr::new("tmx"))
@@ -39,36 +61,164 @@ pub fn find_levels() -> Vec<String> {
     level_list
 }
 
-pub fn load_level(level_name: &str, world: &mut World) {
-    // Clear out world first and reset resources.
-    world.delete_all();
-    world.insert(PlayState::Play);
-    // TODO: Make starting resources tunable in data somehow.
-    world.insert(BuildResources { bits: 30 });
+#[derive(Debug)]
+pub enum LevelError {
+    Parse(TiledError),
+    Data(DataError),
+    MissingLayer(String),
+    /// `object` is the id of the object with the property, or `None` for the map itself.
+    MissingProperty { object: Option<u32>, property: String },
+    WrongPropertyType { object: Option<u32>, property: String, expected: &'static str },
+    UnknownTileGid(u32),
+    OutOfBounds { object: u32, x: f32, y: f32 },
+    DuplicateWaypointId { path: String, id: u8 },
+    MissingWaypoint { path: String, id: u8 },
+    UnknownPath(String),
+    /// A path that doesn't end at a base or branch onto other paths.
+    DeadEndPath(String),
+    UnknownEnemy(String),
+    /// A wave group sent from a spawner the level doesn't have.
+    UnknownSpawner(String),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::Parse(e) => write!(f, "Could not parse level: {}", e),
+            LevelError::Data(e) => write!(f, "{}", e),
+            LevelError::MissingLayer(name) => write!(f, "Missing layer \"{}\"", name),
+            LevelError::MissingProperty { object, property } =>
+                write!(f, "{} is missing property \"{}\"", property_owner(*object), property),
+            LevelError::WrongPropertyType { object, property, expected } =>
+                write!(f, "Property \"{}\" of {} should be {}", property, property_owner(*object), expected),
+            LevelError::UnknownTileGid(gid) => write!(f, "Unknown tile gid {}", gid),
+            LevelError::OutOfBounds { object, x, y } =>
+                write!(f, "Object {} at ({}, {}) is outside the grid", object, x, y),
+            LevelError::DuplicateWaypointId { path, id } =>
+                write!(f, "Waypoint id {} is used more than once on path \"{}\"", id, path),
+            LevelError::MissingWaypoint { path, id } =>
+                write!(f, "Waypoint {} does not exist on path \"{}\"", id, path),
+            LevelError::UnknownPath(path) => write!(f, "Unknown path \"{}\"", path),
+            LevelError::DeadEndPath(path) =>
+                write!(f, "Path \"{}\" doesn't end at a base or branch onto other paths", path),
+            LevelError::UnknownEnemy(name) => write!(f, "Unknown enemy \"{}\"", name),
+            LevelError::UnknownSpawner(name) => write!(f, "Unknown spawner \"{}\"", name),
+        }
+    }
+}
+
+impl Error for LevelError {}
+
+fn property_owner(object: Option<u32>) -> String {
+    match object {
+        Some(object) => format!("Object {}", object),
+        None => "The map".to_owned(),
+    }
+}
+
+impl From<TiledError> for LevelError {
+    fn from(e: TiledError) -> Self {
+        LevelError::Parse(e)
+    }
+}
+
+impl From<DataError> for LevelError {
+    fn from(e: DataError) -> Self {
+        LevelError::Data(e)
+    }
+}
+
+/// An entity described by an object in the level, created once the whole level is valid.
+enum LevelObject {
+    Base(PathPoint),
+    Spawner(Spawner),
+    Waypoint(PathPoint),
+}
+
+/// Where a base or waypoint sits on the level's paths.
+struct PathPoint {
+    path: String,
+    waypoint_id: u8,
+    /// Paths enemies can continue onto after this waypoint, with their weights.
+    branches: Vec<(String, u32)>,
+}
+
+/// Load a level into the world, replacing whatever was there.
+///
+/// The level is fully read and checked before the world is touched, so on error the previous
+/// level is left as is.
+pub fn load_level(level_name: &str, world: &mut World) -> Result<(), LevelError> {
+    // Reload the catalogs too, so they can be tuned without restarting the game.
+    let towers = TowerCatalog::load()?;
+    let enemies = EnemyCatalog::load()?;
 
     let level_path = format!("{}/{}.tmx", LEVEL_DIR, level_name);
-    let map = tiled::parse_file(level_path)
-        .expect("Could not parse level");
+    let map = tiled::parse_file(level_path)?;
+    let settings = level_settings_from_properties(&map.properties)?;
 
-    // Initialize Grid from Grid layer.
+    // Waves are optional and live next to the level in "<level>.waves.toml". Without them, each
+    // spawner sends a single group of enemies on its own.
+    let waves_path = format!("{}/{}.waves.toml", LEVEL_DIR, level_name);
+    let schedule: Option<WaveSchedule> = if Path::new(&waves_path).exists() {
+        Some(load_toml(&waves_path)?)
+    } else {
+        None
+    };
+    if let Some(schedule) = &schedule {
+        for group in schedule.waves.iter().flat_map(|wave| &wave.groups) {
+            if enemies.get(&group.enemy).is_none() {
+                return Err(LevelError::UnknownEnemy(group.enemy.clone()));
+            }
+        }
+    }
+
+    // Initialize Grid from Grid layers. Later layers paint over earlier ones.
     let mut grid = Grid::new(map.width, map.height, map.tile_width as f32);
-    // TODO: Don't hard-code the layer. Check the name at least.
-    for (j, row) in map.layers[0].tiles.iter().enumerate() {
-        for (i, tile) in row.iter().enumerate() {
-            match tile {
-                1 => { grid.set_cell(i as u32, j as u32, GridCell::Buildable); }
-                2 => { grid.set_cell(i as u32, j as u32, GridCell::Walkable); }
-                _ => {}
+    if !map.layers.iter().any(|layer| layer.name == GRID_LAYER) {
+        return Err(LevelError::MissingLayer(GRID_LAYER.to_owned()));
+    }
+    for layer in &map.layers {
+        match layer.name.as_ref() {
+            GRID_LAYER => {
+                for (j, row) in layer.tiles.iter().enumerate() {
+                    for (i, tile) in row.iter().enumerate() {
+                        match tile {
+                            0 => {}
+                            1 => { grid.set_cell(i as u32, j as u32, GridCell::Buildable); }
+                            2 => { grid.set_cell(i as u32, j as u32, GridCell::Walkable); }
+                            gid => return Err(LevelError::UnknownTileGid(*gid)),
+                        }
+                    }
+                }
             }
+            DECORATION_LAYER => {}
+            name => println!("Warning: Ignoring tile layer with unknown name \"{}\"", name),
         }
     }
 
-    // Iterate over objects. Create Waypoints, Spawners, and Bases.
-    for object in &map.object_groups[0].objects {
-        let obj_type = if let (true, Some(tileset)) = (object.obj_type.is_empty(), map.get_tileset_by_gid(object.gid)) {
+    // Gather objects from all object groups with entities in them.
+    if !map.object_groups.iter().any(|group| group.name == ENTITIES_GROUP) {
+        return Err(LevelError::MissingLayer(ENTITIES_GROUP.to_owned()));
+    }
+    let mut objects = Vec::new();
+    for object_group in &map.object_groups {
+        match object_group.name.as_ref() {
+            ENTITIES_GROUP | PATHS_GROUP | WAVES_GROUP => objects.extend(&object_group.objects),
+            name => println!("Warning: Ignoring object group with unknown name \"{}\"", name),
+        }
+    }
+
+    // Iterate over objects. Collect Waypoints, Spawners, and Bases.
+    let mut level_objects = Vec::new();
+    let mut waypoint_ids = HashSet::new();
+    // The last waypoint on each path, and whether it's a base.
+    let mut path_ends: HashMap<String, (u8, bool)> = HashMap::new();
+    for object in objects {
+        let obj_type = if object.obj_type.is_empty() && object.gid != 0 {
             // This tile object didn't set a type, so get the default object type from its tileset.
-            let tile_id = object.gid - tileset.first_gid;
-            let tile = &tileset.tiles[tile_id as usize];
+            let tile = map.get_tileset_by_gid(object.gid)
+                .and_then(|tileset| tileset.tiles.get((object.gid - tileset.first_gid) as usize))
+                .ok_or(LevelError::UnknownTileGid(object.gid))?;
             if let Some(tile_type) = &tile.tile_type {
                 tile_type.as_ref()
             } else {
@@ -77,51 +227,309 @@ pub fn load_level(level_name: &str, world: &mut World) {
         } else {
             object.obj_type.as_ref()
         };
-        let (x, y) = (object.x + object.width as f32 / 2.0,
-                      object.y + object.height as f32 / 2.0);
-        let (cell_x, cell_y) = ((x / grid.cell_size) as u32, (y / grid.cell_size) as u32);
-        match obj_type {
-            "base" => {
-                if let Some(PropertyValue::IntValue(waypoint_id)) = object.properties.get("waypoint_id") {
-                    world.create_entity()
-                        .with(Base {})
-                        .with(Waypoint {id: *waypoint_id as u8})
-                        .with(Transform::new(x, y))
-                        .with(Drawable::Base)
-                        .with(Faction::Player)
-                        .with(Health { current_hp: 1 })
-                        .with(Collider::new(40.0, 40.0))
-                        .build();
-                    grid.set_cell(cell_x, cell_y, GridCell::Occupied);
-                } else {
-                    panic!("Could not find waypoint_id property for base");
+        // Tile objects are positioned by their bottom-left corner, other objects by their top-left.
+        let (x, y) = if object.gid != 0 {
+            (object.x + object.width as f32 / 2.0,
+             object.y - object.height as f32 / 2.0)
+        } else {
+            (object.x + object.width as f32 / 2.0,
+             object.y + object.height as f32 / 2.0)
+        };
+        if x < 0.0 || y < 0.0 || grid.get_cell((x / grid.cell_size) as u32, (y / grid.cell_size) as u32).is_none() {
+            return Err(LevelError::OutOfBounds { object: object.id, x, y });
+        }
+        let level_object = match obj_type {
+            "base" => LevelObject::Base(path_point_from_properties(object)?),
+            "spawner" => {
+                let mut spawner = Spawner::new(&object.name);
+                if let Some(path) = string_property(&object.properties, Some(object.id), "path")? {
+                    spawner.path = path.to_owned();
+                }
+                if let Some(waypoint_id) = u8_property(&object.properties, Some(object.id), "first_waypoint_id")? {
+                    spawner.first_waypoint = waypoint_id;
                 }
+                // Waves take over spawning when the level has them.
+                if schedule.is_none() {
+                    let group = spawn_group_from_properties(object)?;
+                    if enemies.get(&group.enemy).is_none() {
+                        return Err(LevelError::UnknownEnemy(group.enemy));
+                    }
+                    spawner.groups.push(group);
+                } else if SPAWN_GROUP_PROPERTIES.iter().any(|name| object.properties.contains_key(*name)) {
+                    println!("Warning: Ignoring spawn properties of spawner \"{}\" since the level has waves",
+                             object.name);
+                }
+                LevelObject::Spawner(spawner)
             }
-            "spawner" => {
+            "waypoint" => LevelObject::Waypoint(path_point_from_properties(object)?),
+            // Warn since this is an unknown object type.
+            obj_type => {
+                println!("Warning: Ignoring object of unknown type \"{}\"", obj_type);
+                continue;
+            }
+        };
+        let path_point = match &level_object {
+            LevelObject::Base(point) => Some((point, true)),
+            LevelObject::Waypoint(point) => Some((point, false)),
+            LevelObject::Spawner(_) => None,
+        };
+        if let Some((point, is_base)) = path_point {
+            if !waypoint_ids.insert((point.path.clone(), point.waypoint_id)) {
+                return Err(LevelError::DuplicateWaypointId { path: point.path.clone(), id: point.waypoint_id });
+            }
+            let end = path_ends.entry(point.path.clone()).or_insert((point.waypoint_id, is_base));
+            if point.waypoint_id >= end.0 {
+                *end = (point.waypoint_id, is_base);
+            }
+        }
+        level_objects.push((level_object, x, y));
+    }
+
+    // Make sure every spawner has somewhere to send its enemies, and every path leads somewhere.
+    for (level_object, _, _) in &mut level_objects {
+        match level_object {
+            LevelObject::Spawner(spawner) => {
+                if !waypoint_ids.contains(&(spawner.path.clone(), spawner.first_waypoint)) {
+                    return Err(LevelError::MissingWaypoint {
+                        path: spawner.path.clone(),
+                        id: spawner.first_waypoint,
+                    });
+                }
+            }
+            LevelObject::Base(point) | LevelObject::Waypoint(point) => {
+                if let Some(branch) = point.branches.iter().find(|(branch, _)| !path_ends.contains_key(branch)) {
+                    return Err(LevelError::UnknownPath(branch.0.clone()));
+                }
+                if !point.branches.is_empty() && path_ends[&point.path].0 != point.waypoint_id {
+                    println!("Warning: Ignoring branches of waypoint {} since it isn't the last on path \"{}\"",
+                             point.waypoint_id, point.path);
+                    point.branches.clear();
+                }
+            }
+        }
+    }
+    for (path, &(last_id, is_base)) in &path_ends {
+        let branches = level_objects.iter().any(|(level_object, _, _)| match level_object {
+            LevelObject::Base(point) | LevelObject::Waypoint(point) =>
+                point.path == *path && point.waypoint_id == last_id && !point.branches.is_empty(),
+            LevelObject::Spawner(_) => false,
+        });
+        if !is_base && !branches {
+            return Err(LevelError::DeadEndPath(path.clone()));
+        }
+    }
+
+    // Make sure every wave group is sent from a spawner that exists.
+    if let Some(schedule) = &schedule {
+        let spawner_names: HashSet<&str> = level_objects.iter()
+            .filter_map(|(level_object, _, _)| match level_object {
+                LevelObject::Spawner(spawner) => Some(spawner.name.as_str()),
+                _ => None,
+            })
+            .collect();
+        let unknown = schedule.waves.iter()
+            .flat_map(|wave| &wave.groups)
+            .filter_map(|group| group.spawner.as_ref())
+            .find(|name| !spawner_names.contains(name.as_str()));
+        if let Some(name) = unknown {
+            return Err(LevelError::UnknownSpawner(name.clone()));
+        }
+    }
+
+    // The level is valid, so clear out world and reset resources.
+    world.delete_all();
+    world.insert(PlayState::Play);
+    world.insert(BuildResources { bits: settings.starting_bits });
+    world.insert(settings);
+    world.insert(towers);
+    world.insert(enemies);
+
+    // Create Waypoints, Spawners, and Bases.
+    let mut paths: HashMap<String, NavPath> = HashMap::new();
+    for (level_object, x, y) in level_objects {
+        let (cell_x, cell_y) = ((x / grid.cell_size) as u32, (y / grid.cell_size) as u32);
+        match level_object {
+            LevelObject::Base(point) => {
+                let entity = world.create_entity()
+                    .with(Base {})
+                    .with(Waypoint {id: point.waypoint_id, path: point.path.clone()})
+                    .with(Transform::new(x, y))
+                    .with(Drawable::Base)
+                    .with(Faction::Player)
+                    .with(Health::new(settings.base_hp))
+                    .build();
+                add_to_path(&mut paths, point, entity, (cell_x, cell_y));
+            }
+            LevelObject::Spawner(spawner) => {
                 world.create_entity()
-                    .with(Spawner::default())
+                    .with(spawner)
                     .with(Transform::new(x, y))
                     .with(Drawable::Spawner)
                     .build();
-                grid.set_cell(cell_x, cell_y, GridCell::Occupied);
             }
-            "waypoint" => {
-                if let Some(PropertyValue::IntValue(waypoint_id)) = object.properties.get("waypoint_id") {
-                    world.create_entity()
-                        .with(Waypoint {id: *waypoint_id as u8})
-                        .with(Transform::new(x, y))
-                        .with(Drawable::Waypoint)
-                        .build();
-                    grid.set_cell(cell_x, cell_y, GridCell::Occupied);
-                } else {
-                    panic!("Could not find waypoint_id property for base");
-                }
+            LevelObject::Waypoint(point) => {
+                let entity = world.create_entity()
+                    .with(Waypoint {id: point.waypoint_id, path: point.path.clone()})
+                    .with(Transform::new(x, y))
+                    .with(Drawable::Waypoint)
+                    .build();
+                add_to_path(&mut paths, point, entity, (cell_x, cell_y));
             }
-            // Warn since this is an unknown object type.
-            obj_type => println!("Warning: Ignoring object of unknown type \"{}\"", obj_type),
         }
+        grid.set_cell(cell_x, cell_y, GridCell::Occupied);
     }
 
     // Insert initial resources.
+    // The flow fields are built from the finished grid, so there are no changes to catch up on.
+    grid.take_changes();
+    world.insert(Navigation::new(&grid, settings.nav_mode, paths));
+    world.insert(Random::default());
     world.insert(grid);
+    world.insert(Waves::new(schedule.unwrap_or_default()));
+
+    Ok(())
+}
+
+/// Read the level's settings from the map's properties, using defaults for any that are missing.
+fn level_settings_from_properties(properties: &Properties) -> Result<LevelSettings, LevelError> {
+    let mut settings = LevelSettings::default();
+    if let Some(starting_bits) = int_property(properties, None, "starting_bits")? {
+        settings.starting_bits = starting_bits.max(0) as u32;
+    }
+    if let Some(bounty_multiplier) = float_property(properties, None, "bounty_multiplier")? {
+        settings.bounty_multiplier = bounty_multiplier;
+    }
+    if let Some(base_hp) = int_property(properties, None, "base_hp")? {
+        settings.base_hp = base_hp.max(1) as u32;
+    }
+    if let Some(interest_rate) = float_property(properties, None, "interest_rate")? {
+        settings.interest_rate = interest_rate;
+    }
+    if let Some(sell_refund) = float_property(properties, None, "sell_refund")? {
+        settings.sell_refund = sell_refund.clamp(0.0, 1.0);
+    }
+    // In maze levels, enemies walk around towers instead of following painted paths.
+    if let Some(true) = bool_property(properties, None, "maze")? {
+        settings.nav_mode = NavMode::Maze;
+    }
+    Ok(settings)
+}
+
+/// Build a spawner's own group of enemies from its properties, using defaults for any that are
+/// missing.
+fn spawn_group_from_properties(object: &Object) -> Result<SpawnGroup, LevelError> {
+    let (properties, id) = (&object.properties, Some(object.id));
+    let mut group = SpawnGroup::default();
+    if let Some(enemy) = string_property(properties, id, "enemy")? {
+        group.enemy = enemy.to_owned();
+    }
+    if let Some(count) = int_property(properties, id, "count")? {
+        group.count = count.max(0) as u32;
+    }
+    if let Some(seconds_to_spawn) = float_property(properties, id, "seconds_to_spawn")? {
+        group.seconds_to_spawn = seconds_to_spawn;
+    }
+    if let Some(start_delay) = float_property(properties, id, "start_delay")? {
+        group.cooldown = start_delay;
+    }
+    Ok(group)
+}
+
+/// Add a waypoint to its path, taking the path's branches from it if it has any.
+fn add_to_path(paths: &mut HashMap<String, NavPath>, point: PathPoint, entity: Entity, cell: (u32, u32)) {
+    let path = paths.entry(point.path).or_default();
+    path.waypoints.push(NavWaypoint { id: point.waypoint_id, entity, cell });
+    if !point.branches.is_empty() {
+        path.branches = point.branches;
+    }
+}
+
+/// Read which path a base or waypoint is on, its id on the path, and the paths that branch off
+/// after it.
+///
+/// Branches are listed as path names separated by commas, each with an optional weight after a
+/// colon, e.g. "north:3, south". Weights default to 1.
+fn path_point_from_properties(object: &Object) -> Result<PathPoint, LevelError> {
+    let path = string_property(&object.properties, Some(object.id), "path")?.unwrap_or_default();
+    let mut branches = Vec::new();
+    if let Some(value) = string_property(&object.properties, Some(object.id), "branches")? {
+        for branch in value.split(',').map(str::trim).filter(|branch| !branch.is_empty()) {
+            let mut parts = branch.splitn(2, ':');
+            let name = parts.next().unwrap_or_default().trim();
+            let weight = match parts.next() {
+                Some(weight) => weight.trim().parse()
+                    .map_err(|_| wrong_property_type(Some(object.id), "branches", "a list of paths with weights"))?,
+                None => 1,
+            };
+            branches.push((name.to_owned(), weight));
+        }
+    }
+    Ok(PathPoint {
+        path: path.to_owned(),
+        waypoint_id: waypoint_id_property(object)?,
+        branches,
+    })
+}
+
+fn waypoint_id_property(object: &Object) -> Result<u8, LevelError> {
+    u8_property(&object.properties, Some(object.id), "waypoint_id")?
+        .ok_or_else(|| LevelError::MissingProperty {
+            object: Some(object.id),
+            property: "waypoint_id".to_owned(),
+        })
+}
+
+// Property getters. `object` is the id of the object the properties belong to, or `None` for the
+// map's own properties.
+
+fn wrong_property_type(object: Option<u32>, name: &str, expected: &'static str) -> LevelError {
+    LevelError::WrongPropertyType {
+        object,
+        property: name.to_owned(),
+        expected,
+    }
+}
+
+fn int_property(properties: &Properties, object: Option<u32>, name: &str) -> Result<Option<i32>, LevelError> {
+    match properties.get(name) {
+        Some(PropertyValue::IntValue(value)) => Ok(Some(*value)),
+        Some(_) => Err(wrong_property_type(object, name, "an int")),
+        None => Ok(None),
+    }
+}
+
+/// Like `int_property`, but the int has to fit in a `u8`.
+fn u8_property(properties: &Properties, object: Option<u32>, name: &str) -> Result<Option<u8>, LevelError> {
+    match int_property(properties, object, name)? {
+        Some(value) => u8::try_from(value)
+            .map(Some)
+            .map_err(|_| wrong_property_type(object, name, "an int from 0 to 255")),
+        None => Ok(None),
+    }
+}
+
+/// Int properties are accepted too, since Tiled makes it easy to pick the wrong numeric type.
+fn float_property(properties: &Properties, object: Option<u32>, name: &str) -> Result<Option<f32>, LevelError> {
+    match properties.get(name) {
+        Some(PropertyValue::FloatValue(value)) => Ok(Some(*value)),
+        Some(PropertyValue::IntValue(value)) => Ok(Some(*value as f32)),
+        Some(_) => Err(wrong_property_type(object, name, "a float")),
+        None => Ok(None),
+    }
+}
+
+fn bool_property(properties: &Properties, object: Option<u32>, name: &str) -> Result<Option<bool>, LevelError> {
+    match properties.get(name) {
+        Some(PropertyValue::BoolValue(value)) => Ok(Some(*value)),
+        Some(_) => Err(wrong_property_type(object, name, "a bool")),
+        None => Ok(None),
+    }
+}
+
+fn string_property<'a>(properties: &'a Properties, object: Option<u32>, name: &str) -> Result<Option<&'a str>, LevelError> {
+    match properties.get(name) {
+        Some(PropertyValue::StringValue(value)) => Ok(Some(value)),
+        Some(_) => Err(wrong_property_type(object, name, "a string")),
+        None => Ok(None),
+    }
 }
@@ -0,0 +1,16 @@
+//! Game logic for Isengard Returns.
+//!
+//! Everything needed to load and simulate a level lives here, independent of ggez's event loop,
+//! so it can be shared by the game binary, tools, and integration tests.
+
+pub mod components;
+pub mod damage;
+pub mod data;
+pub mod grid;
+pub mod level;
+pub mod pathfinding;
+pub mod rect;
+pub mod resources;
+pub mod simulation;
+pub mod systems;
+pub mod towers;
@@ -8,19 +8,24 @@ use ggez::input::{
 use ggez::nalgebra::Point2;
 use specs::prelude::*;
 
-use components::*;
+use isengard_returns::{
+    components::*,
+    data::*,
+    grid::*,
+    level::{self, LevelError},
+    resources::*,
+    simulation::*,
+    towers::{build_tower, sell_tower, upgrade_tower, BuildCheck},
+};
+
 use debug_ui::*;
-use grid::*;
-use resources::*;
-use systems::*;
 
-mod components;
 mod debug_ui;
-mod grid;
-mod level;
-mod rect;
-mod resources;
-mod systems;
+
+/// Fixed time step used when running a level without a window.
+const HEADLESS_DT: f32 = 1.0 / 60.0;
+/// Give up on a headless run after this many steps (10 minutes of game time).
+const HEADLESS_MAX_STEPS: u32 = 60 * 60 * 10;
 
 #[derive(Clone, Debug)]
 enum LoadLevelRequest {
@@ -31,12 +36,20 @@ enum LoadLevelRequest {
 
 struct State<'a, 'b> {
     // Game state.
-    world: World,
-    dispatcher: Dispatcher<'a, 'b>,
+    simulation: Simulation<'a, 'b>,
     current_level: String,
     level_request: LoadLevelRequest,
-
-    // TODO: Place UI state.
+    /// Why the last level load failed, shown until a level loads successfully.
+    level_error: Option<String>,
+
+    // UI state.
+    /// Index into the `TowerCatalog` of the tower to build on click.
+    build_tower: usize,
+    /// Tower the player last clicked on.
+    selected_tower: Option<Entity>,
+    /// Build check for the hovered cell, with the grid revision it was made for. Cleared when a
+    /// level loads.
+    hover_build: Option<(u64, BuildCheck)>,
 
     debug_ui: DebugUi,
     // Debug UI state.
@@ -51,42 +64,25 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
             return;
         }
 
-        // TODO: Move this to a system.
-        // If the player clicks on an open spot on the grid and has enough bits, then build a tower.
-        if button == MouseButton::Left &&
-            *self.world.read_resource::<PlayState>() == PlayState::Play &&
-            self.world.read_resource::<BuildResources>().bits >= 10 {
-            // Check which grid cell we've clicked. If nothing is there, build a tower.
-            let world_pos = {
-                let mut grid = self.world.write_resource::<Grid>();
-                let (cell_x, cell_y) = ((x / grid.cell_size) as u32, (y / grid.cell_size) as u32);
-                if grid.is_buildable(cell_x, cell_y) {
-                    // FIXME: We might wanna defer this to after the tower actually exists. In case
-                    // something goes wrong in creating it.
-                    grid.set_cell(cell_x, cell_y, GridCell::Occupied);
-                    // Return the center of the cell in world coordinates.
-                    Some(((cell_x as f32 * grid.cell_size) + grid.cell_size / 2.0,
-                          (cell_y as f32 * grid.cell_size) + grid.cell_size / 2.0))
-                } else {
-                    None
-                }
-            };
-
-            // Try to build a tower at the location clicked.
-            if let Some((world_x, world_y)) = world_pos {
-                self.world.create_entity()
-                    .with(Transform::new(world_x, world_y))
-                    .with(Drawable::Tower)
-                    .with(Faction::Player)
-                    .with(Shooter { seconds_per_attack: 1.0, cooldown: 0.0, attack_radius: 100.0 })
-                    .build();
-
-                // Spend resources!
-                // TODO: Make the cost tunable in data somehow.
-                self.world.write_resource::<BuildResources>().bits -= 10;
+        if button != MouseButton::Left {
+            return;
+        }
+        // Clicking on a tower selects it.
+        let cell = self.simulation.world.read_resource::<Grid>().cell_at(x, y);
+        self.selected_tower = cell.and_then(|cell| tower_at(&self.simulation.world, cell));
+        if self.selected_tower.is_some() {
+            return;
+        }
+        let cell = match cell {
+            Some(cell) => cell,
+            None => return,
+        };
 
-                println!("Built tower at {:?}!", (world_x, world_y));
-            };
+        // If the player clicks on an open spot on the grid and has enough bits, then build a tower.
+        let tower = self.simulation.world.read_resource::<TowerCatalog>()
+            .towers.get(self.build_tower).cloned();
+        if let Some(tower) = tower {
+            build_tower(&mut self.simulation.world, &tower, cell);
         }
     }
 
@@ -108,7 +104,36 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
             KeyCode::Grave => {
                 self.show_debug_ui = !self.show_debug_ui;
             }
-            _ => {}
+            KeyCode::N => {
+                self.simulation.world.write_resource::<Waves>().call_next_wave();
+            }
+            KeyCode::T => {
+                // Cycle what the selected tower shoots at.
+                if let Some(entity) = self.selected_tower {
+                    if let Some(shooter) = self.simulation.world.write_storage::<Shooter>().get_mut(entity) {
+                        shooter.targeting = shooter.targeting.next();
+                    }
+                }
+            }
+            KeyCode::U | KeyCode::I | KeyCode::O => {
+                // Buy one of the selected tower's upgrades.
+                if let (Some(entity), Some(choice)) = (self.selected_tower, upgrade_key_index(keycode)) {
+                    upgrade_tower(&self.simulation.world, entity, choice);
+                }
+            }
+            KeyCode::S => {
+                if let Some(entity) = self.selected_tower.take() {
+                    sell_tower(&self.simulation.world, entity);
+                }
+            }
+            keycode => {
+                // Number keys pick which tower to build.
+                if let Some(index) = number_key_index(keycode) {
+                    if index < self.simulation.world.read_resource::<TowerCatalog>().towers.len() {
+                        self.build_tower = index;
+                    }
+                }
+            }
         }
     }
 
@@ -121,35 +146,23 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
         // TODO: Watch level directory for changes and update level_list if any files are
         // added/removed.
 
-        // Call maintain to update all entities created via input events.
-        self.world.maintain();
-
         if let LoadLevelRequest::Reload = self.level_request {
-            level::load_level(&self.current_level, &mut self.world);
+            let result = self.simulation.load_level(&self.current_level);
+            self.set_level_error(result);
             self.level_request = LoadLevelRequest::None;
+            self.hover_build = None;
         } else if let LoadLevelRequest::NewLevel(level_name) = &self.level_request {
-            level::load_level(level_name, &mut self.world);
-            self.current_level = level_name.clone();
+            let result = self.simulation.load_level(level_name);
+            if result.is_ok() {
+                self.current_level = level_name.clone();
+            }
+            self.set_level_error(result);
             self.level_request = LoadLevelRequest::None;
+            self.hover_build = None;
         } else {
-            {
-                // Sets the time and updates it
-                let duration = timer::duration_to_f64(timer::delta(ctx));
-                self.world.insert(DeltaTime(duration as f32));
-
-                // Clears collision event vector
-                let mut collisions = self.world.write_resource::<Vec<CollisionEvent>>();
-                collisions.clear();
-                let mut death_events = self.world.write_resource::<Vec<DeathEvent>>();
-                death_events.clear();
-            }
-
-            if *self.world.read_resource::<PlayState>() == PlayState::Play {
-                self.dispatcher.dispatch(&mut self.world);
-
-                // Update all entities created/deleted in systems.
-                self.world.maintain();
-            }
+            // Step the simulation by how long the last frame took.
+            let duration = timer::duration_to_f64(timer::delta(ctx));
+            self.simulation.step(duration as f32);
         }
 
         Ok(())
@@ -162,14 +175,41 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
         graphics::clear(ctx, graphics::BLACK);
 
         let system_data: (
+            Entities,
             ReadStorage<Transform>,
             ReadStorage<Drawable>,
+            ReadStorage<Collider>,
+            ReadStorage<BeamSegment>,
             ReadStorage<Shooter>,
+            ReadStorage<Tower>,
+            ReadStorage<Base>,
+            ReadStorage<Health>,
+            ReadStorage<StatusEffects>,
             Read<BuildResources>,
+            Read<LevelSettings>,
+            Read<TowerCatalog>,
             Read<Grid>,
+            Read<Waves>,
             Read<PlayState>,
-        ) = self.world.system_data();
-        let (transforms, drawables, shooters, build_resources, grid, play_state) = system_data;
+        ) = self.simulation.world.system_data();
+        let (
+            entities,
+            transforms,
+            drawables,
+            colliders,
+            beam_segments,
+            shooters,
+            towers,
+            bases,
+            healths,
+            status_effects,
+            build_resources,
+            settings,
+            tower_catalog,
+            grid,
+            waves,
+            play_state,
+        ) = system_data;
 
         // Draw the grid first.
         let grid_mesh = {
@@ -177,6 +217,14 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
             for j in 0..grid.height {
                 for i in 0..grid.width {
                     let (x, y) = (i as f32 * grid.cell_size, j as f32 * grid.cell_size);
+                    // Fill in walkable cells so the enemies' paths stand out.
+                    if grid.is_walkable(i, j) {
+                        mb.rectangle(
+                            graphics::DrawMode::fill(),
+                            graphics::Rect::new(x, y, grid.cell_size, grid.cell_size),
+                            graphics::Color::from_rgb(50, 40, 30),
+                        );
+                    }
                     mb.rectangle(
                         graphics::DrawMode::stroke(2.0),
                         graphics::Rect::new(x, y, grid.cell_size, grid.cell_size),
@@ -190,7 +238,7 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
 
         // TODO: Sort our drawables so enemies are rendered on top of buildings!
 
-        for (transform, drawable) in (&transforms, &drawables).join() {
+        for (entity, transform, drawable) in (&entities, &transforms, &drawables).join() {
             let mesh = match drawable {
                 Drawable::Tower => {
                     graphics::Mesh::new_circle(
@@ -203,11 +251,22 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
                     )?
                 },
                 Drawable::Enemy => {
+                    // Draw enemies as big as their collider.
+                    let (width, height) = colliders.get(entity)
+                        .map(|collider| (collider.width, collider.height))
+                        .unwrap_or((40.0, 40.0));
+                    // Tint enemies by the status effects on them.
+                    let color = match status_effects.get(entity) {
+                        Some(effects) if effects.count(StatusKind::Stun) > 0 => graphics::Color::from_rgb(255, 255, 0),
+                        Some(effects) if effects.count(StatusKind::DamageOverTime) > 0 => graphics::Color::from_rgb(150, 200, 0),
+                        Some(effects) if effects.count(StatusKind::Slow) > 0 => graphics::Color::from_rgb(150, 0, 255),
+                        _ => graphics::Color::from_rgb(255, 0, 0),
+                    };
                     graphics::Mesh::new_rectangle(
                         ctx,
                         graphics::DrawMode::fill(),
-                        graphics::Rect::new_i32(-20, -20, 40, 40),
-                        graphics::Color::from_rgb(255, 0, 0),
+                        graphics::Rect::new(-width / 2.0, -height / 2.0, width, height),
+                        color,
                     )?
                 },
                 Drawable::Projectile => {
@@ -248,11 +307,103 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
                         graphics::Color::from_rgb(100, 100, 100),
                     )?
                 },
+                Drawable::Beam => {
+                    let end = match beam_segments.get(entity) {
+                        Some(segment) => segment.end - transform.position,
+                        None => continue,
+                    };
+                    graphics::Mesh::new_line(
+                        ctx,
+                        &[mint::Point2{x: 0.0, y: 0.0}, mint::Point2{x: end.x, y: end.y}],
+                        3.0,
+                        graphics::Color::from_rgb(0, 255, 255),
+                    )?
+                },
             };
 
             graphics::draw(ctx, &mesh, graphics::DrawParam::default().dest(transform.position))?;
         }
 
+        // Draw how much HP each base has left above it.
+        for (transform, _, health) in (&transforms, &bases, &healths).join() {
+            graphics::draw(
+                ctx,
+                &graphics::Text::new(format!("HP {}", health.current_hp)),
+                graphics::DrawParam::default()
+                    .dest([transform.position.x - 20.0, transform.position.y - 40.0]),
+            )?;
+        }
+
+        // Draw health bars, with any shield left on top, above everything else that can be hurt.
+        for (transform, _, health) in (&transforms, !&bases, &healths).join() {
+            let (x, y) = (transform.position.x - 20.0, transform.position.y - 30.0);
+            let mut mb = graphics::MeshBuilder::new();
+            mb.rectangle(
+                graphics::DrawMode::fill(),
+                graphics::Rect::new(x, y, 40.0, 5.0),
+                graphics::Color::from_rgb(80, 0, 0),
+            );
+            mb.rectangle(
+                graphics::DrawMode::fill(),
+                graphics::Rect::new(x, y, 40.0 * health.fraction(), 5.0),
+                graphics::Color::from_rgb(0, 200, 0),
+            );
+            if let Some(shield) = &health.shield {
+                if shield.current > 0 {
+                    let fraction = shield.current as f32 / shield.max as f32;
+                    mb.rectangle(
+                        graphics::DrawMode::fill(),
+                        graphics::Rect::new(x, y - 4.0, 40.0 * fraction, 3.0),
+                        graphics::Color::from_rgb(0, 150, 255),
+                    );
+                }
+            }
+            let mesh = mb.build(ctx)?;
+            graphics::draw(ctx, &mesh, graphics::DrawParam::default())?;
+        }
+
+        // Outline the selected tower and show what it's targeting and the upgrades it can buy.
+        let selected = self.selected_tower
+            .and_then(|entity| Some((transforms.get(entity)?, shooters.get(entity)?, towers.get(entity)?)));
+        if let Some((transform, shooter, tower)) = selected {
+            let mesh = graphics::Mesh::new_circle(
+                ctx,
+                graphics::DrawMode::stroke(3.0),
+                mint::Point2{x: 0.0, y: 0.0},
+                24.0,
+                0.1,
+                graphics::Color::from_rgb(255, 255, 0),
+            )?;
+            graphics::draw(ctx, &mesh, graphics::DrawParam::default().dest(transform.position))?;
+            graphics::draw(
+                ctx,
+                graphics::Text::new(format!("Targeting: {:?} (T to change)", shooter.targeting))
+                    .set_bounds(Point2::new(400.0, f32::INFINITY), graphics::Align::Right),
+                graphics::DrawParam::default()
+                    .dest([390.0, 50.0]),
+            )?;
+            graphics::draw(
+                ctx,
+                graphics::Text::new(format!("S: Sell ({} bits)", tower.sell_value(settings.sell_refund)))
+                    .set_bounds(Point2::new(400.0, f32::INFINITY), graphics::Align::Right),
+                graphics::DrawParam::default()
+                    .dest([390.0, 70.0]),
+            )?;
+            let upgrade_options = tower_catalog.get(&tower.name)
+                .map(|tower_def| tower_def.upgrade_options(&tower.upgrades))
+                .unwrap_or_default();
+            let upgrade_keys = ["U", "I", "O"];
+            for (i, (upgrade, key)) in upgrade_options.iter().zip(upgrade_keys.iter()).enumerate() {
+                graphics::draw(
+                    ctx,
+                    graphics::Text::new(format!("{}: Upgrade to {} ({} bits)", key, upgrade.name, upgrade.cost))
+                        .set_bounds(Point2::new(400.0, f32::INFINITY), graphics::Align::Right),
+                    graphics::DrawParam::default()
+                        .dest([390.0, 90.0 + 20.0 * i as f32]),
+                )?;
+            }
+        }
+
         // Draw shooter's attack radius.
         if input::keyboard::is_mod_active(ctx, KeyMods::ALT) {
             for (transform, shooter) in (&transforms, &shooters).join() {
@@ -270,9 +421,17 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
 
         // Highlight the grid cell the mouse is hovering over.
         let mouse_pos = input::mouse::position(ctx);
-        let (cell_x, cell_y) = ((mouse_pos.x / grid.cell_size) as u32, (mouse_pos.y / grid.cell_size) as u32);
-        if let Some(cell) = grid.get_cell(cell_x, cell_y) {
-            let color = if cell == GridCell::Buildable {
+        if let Some((cell_x, cell_y)) = grid.cell_at(mouse_pos.x, mouse_pos.y) {
+            // Checking a maze means pathfinding, so only make a new check once the hovered cell or
+            // the grid changes. Enemies move every frame, so they're checked every frame.
+            let revision = grid.revision();
+            let check = match self.hover_build.take() {
+                Some((checked, check)) if checked == revision && check.cell() == (cell_x, cell_y) => check,
+                _ => BuildCheck::new(&self.simulation.world, (cell_x, cell_y)),
+            };
+            let buildable = check.allows(&self.simulation.world);
+            self.hover_build = Some((revision, check));
+            let color = if buildable {
                 graphics::Color::from_rgba(0, 0, 127, 127)
             } else {
                 graphics::Color::from_rgba(127, 0, 0, 127)
@@ -297,6 +456,30 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
                 .dest([390.0, 10.0]),
         )?;
 
+        // Draw which tower will be built.
+        if let Some(tower) = tower_catalog.towers.get(self.build_tower) {
+            graphics::draw(
+                ctx,
+                graphics::Text::new(format!("Build: {} ({} bits)", tower.name, tower.cost))
+                    .set_bounds(Point2::new(400.0, f32::INFINITY), graphics::Align::Right),
+                graphics::DrawParam::default()
+                    .dest([390.0, 30.0]),
+            )?;
+        }
+
+        // Draw the countdown to the next wave.
+        if *play_state == PlayState::Play && !waves.is_finished() {
+            graphics::draw(
+                ctx,
+                &graphics::Text::new(format!("Wave {}/{} in {:.0}s (N to call early)",
+                                             waves.next_wave + 1,
+                                             waves.schedule.waves.len(),
+                                             waves.countdown.max(0.0))),
+                graphics::DrawParam::default()
+                    .dest([10.0, 10.0]),
+            )?;
+        }
+
         match *play_state {
             PlayState::Win => {
                 graphics::draw(
@@ -317,6 +500,17 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
             _ => {}
         }
 
+        // Show why the last level failed to load.
+        if let Some(level_error) = &self.level_error {
+            graphics::draw(
+                ctx,
+                &graphics::Text::new(format!("Could not load level: {}", level_error)),
+                graphics::DrawParam::default()
+                    .dest([10.0, 570.0])
+                    .color(graphics::Color::from_rgb(255, 80, 80)),
+            )?;
+        }
+
         // Build and draw the debug UI.
         if self.show_debug_ui {
             let level_request = &mut self.level_request;
@@ -354,30 +548,23 @@ impl<'a, 'b> ggez::event::EventHandler for State<'a, 'b> {
 }
 
 impl<'a, 'b> State<'a, 'b> {
+    fn set_level_error(&mut self, result: Result<(), LevelError>) {
+        self.level_error = match result {
+            Ok(()) => None,
+            Err(e) => {
+                println!("Could not load level: {}", e);
+                Some(e.to_string())
+            }
+        };
+    }
+
     fn new(ctx: &mut Context) -> GameResult<Self> {
-        // Set up the specs world.
-        let mut world = World::new();
-        world.register::<Transform>();
-        world.register::<Drawable>();
-        // Currently the Projectile resource isn't accessed by any systems so it needs to be registered here.
-        world.register::<Projectile>();
-
-        let mut dispatcher = DispatcherBuilder::new()
-            .with(EnemyAi, "enemy_ai", &[])
-            .with(ShooterSystem, "shooter_system", &["enemy_ai"])
-            .with(UpdatePosition, "update_position", &["shooter_system"])
-            .with(CollisionSystem, "collision_system", &["update_position"])
-            .with(AttackSystem, "attack_system", &["collision_system"])
-            .with(SpawnerSystem, "spawner_system", &["attack_system"])
-            .with(DeathSystem, "death_system", &["spawner_system"])
-            .with(WinSystem, "win_system", &["death_system"])
-            .build();
-
-        dispatcher.setup(&mut world);
+        let mut simulation = Simulation::new();
 
         // Load the level!
         let start_level = "test";
-        level::load_level(start_level, &mut world);
+        simulation.load_level(start_level)
+            .map_err(|e| GameError::ResourceLoadError(e.to_string()))?;
 
         // Initialize the debug UI.
         let debug_ui = DebugUi::new(ctx);
@@ -385,10 +572,14 @@ impl<'a, 'b> State<'a, 'b> {
         let level_list = level::find_levels();
 
         Ok(Self {
-            world,
-            dispatcher,
+            simulation,
             current_level: start_level.to_owned(),
             level_request: LoadLevelRequest::None,
+            level_error: None,
+
+            build_tower: 0,
+            selected_tower: None,
+            hover_build: None,
 
             debug_ui,
             show_debug_ui: false,
@@ -397,7 +588,69 @@ impl<'a, 'b> State<'a, 'b> {
     }
 }
 
+/// Get the tower built on a grid cell, if there is one.
+fn tower_at(world: &World, cell: (u32, u32)) -> Option<Entity> {
+    let (entities, transforms, shooters, grid): (Entities, ReadStorage<Transform>, ReadStorage<Shooter>, Read<Grid>) =
+        world.system_data();
+    (&entities, &transforms, &shooters).join()
+        .find(|(_, transform, _)| grid.cell_at(transform.position.x, transform.position.y) == Some(cell))
+        .map(|(entity, _, _)| entity)
+}
+
+/// Map the keys that buy upgrades to the index of the upgrade option they buy.
+fn upgrade_key_index(keycode: KeyCode) -> Option<usize> {
+    match keycode {
+        KeyCode::U => Some(0),
+        KeyCode::I => Some(1),
+        KeyCode::O => Some(2),
+        _ => None,
+    }
+}
+
+/// Map the number keys 1 through 9 to the indices 0 through 8.
+fn number_key_index(keycode: KeyCode) -> Option<usize> {
+    match keycode {
+        KeyCode::Key1 => Some(0),
+        KeyCode::Key2 => Some(1),
+        KeyCode::Key3 => Some(2),
+        KeyCode::Key4 => Some(3),
+        KeyCode::Key5 => Some(4),
+        KeyCode::Key6 => Some(5),
+        KeyCode::Key7 => Some(6),
+        KeyCode::Key8 => Some(7),
+        KeyCode::Key9 => Some(8),
+        _ => None,
+    }
+}
+
+/// Play a level to completion with a fixed time step and no window, then report the outcome.
+///
+/// Exits with a non-zero code unless the level was won, so scripts and CI can tell outcomes
+/// apart: 1 if the level failed to load, 2 if it was lost and 3 if it ran out of steps.
+fn run_headless(level_name: &str) {
+    let mut simulation = Simulation::new();
+    if let Err(e) = simulation.load_level(level_name) {
+        println!("Could not load level \"{}\": {}", level_name, e);
+        std::process::exit(1);
+    }
+    let play_state = simulation.run(HEADLESS_DT, HEADLESS_MAX_STEPS);
+    println!("Level \"{}\" finished with {:?}", level_name, play_state);
+    match play_state {
+        PlayState::Win => {}
+        PlayState::Lose => std::process::exit(2),
+        PlayState::Play => std::process::exit(3),
+    }
+}
+
 fn main() -> GameResult {
+    // Run without creating a window when passed "--headless [level]".
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--headless") {
+        let level_name = args.get(2).map(String::as_str).unwrap_or("test");
+        run_headless(level_name);
+        return Ok(());
+    }
+
     let (mut ctx, mut event_loop) = ContextBuilder::new("isengard_returns", "studio_giblets")
         .add_resource_path("./assets")
         .window_setup(conf::WindowSetup::default().title("Isengard Returns!"))
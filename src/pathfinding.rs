@@ -0,0 +1,465 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+use rand::Rng;
+use specs::Entity;
+
+use crate::grid::*;
+
+/// Which cells enemies can walk through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NavMode {
+    /// Only walkable cells, so enemies stick to the paths painted in the level.
+    Paths,
+    /// Any open cell, so the towers the player builds form a maze.
+    Maze,
+}
+
+impl Default for NavMode {
+    fn default() -> Self {
+        NavMode::Paths
+    }
+}
+
+impl NavMode {
+    pub fn is_passable(self, grid: &Grid, x: u32, y: u32) -> bool {
+        match self {
+            NavMode::Paths => grid.is_walkable(x, y),
+            NavMode::Maze => grid.is_open(x, y),
+        }
+    }
+}
+
+/// Offsets to the cells enemies can step to from any cell.
+const NEIGHBORS: [(i64, i64); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+/// Get the cells next to `cell` that are on the grid.
+pub fn neighbors(grid: &Grid, cell: (u32, u32)) -> impl Iterator<Item = (u32, u32)> + '_ {
+    NEIGHBORS.iter().filter_map(move |(dx, dy)| {
+        let (x, y) = (cell.0 as i64 + dx, cell.1 as i64 + dy);
+        if x >= 0 && y >= 0 && grid.in_bounds(x as u32, y as u32) {
+            Some((x as u32, y as u32))
+        } else {
+            None
+        }
+    })
+}
+
+/// Distance in steps from every cell to a goal cell, following passable cells.
+///
+/// Enemies anywhere on the grid can find their way to the goal by stepping to the neighboring
+/// cell with the smallest distance.
+#[derive(Clone, Debug)]
+pub struct FlowField {
+    pub goal: (u32, u32),
+    width: u32,
+    distances: Vec<Option<u32>>,
+}
+
+impl FlowField {
+    pub fn new(grid: &Grid, mode: NavMode, goal: (u32, u32)) -> Self {
+        let mut field = Self {
+            goal,
+            width: grid.width,
+            distances: vec![None; (grid.width * grid.height) as usize],
+        };
+
+        // Breadth-first search out from the goal.
+        let mut open = VecDeque::new();
+        field.set_distance(goal, 0);
+        open.push_back(goal);
+        while let Some(cell) = open.pop_front() {
+            let distance = field.distance(cell).unwrap_or(0) + 1;
+            for neighbor in neighbors(grid, cell) {
+                if field.distance(neighbor).is_none() && mode.is_passable(grid, neighbor.0, neighbor.1) {
+                    field.set_distance(neighbor, distance);
+                    open.push_back(neighbor);
+                }
+            }
+        }
+
+        field
+    }
+
+    /// Steps from `cell` to the goal, or `None` if the goal can't be reached from it.
+    pub fn distance(&self, cell: (u32, u32)) -> Option<u32> {
+        self.distances.get((cell.1 * self.width + cell.0) as usize).cloned().flatten()
+    }
+
+    /// Like `distance`, but also works from impassable cells, like the spawner or waypoint an
+    /// enemy is leaving, by stepping off them first.
+    pub fn distance_from(&self, grid: &Grid, cell: (u32, u32)) -> Option<u32> {
+        self.distance(cell).or_else(|| {
+            neighbors(grid, cell)
+                .filter_map(|neighbor| self.distance(neighbor))
+                .min()
+                .map(|distance| distance + 1)
+        })
+    }
+
+    fn set_distance(&mut self, cell: (u32, u32), distance: u32) {
+        self.distances[(cell.1 * self.width + cell.0) as usize] = Some(distance);
+    }
+
+    fn clear_distance(&mut self, cell: (u32, u32)) {
+        self.distances[(cell.1 * self.width + cell.0) as usize] = None;
+    }
+
+    /// Whether `distance` is shorter than the known distance from `cell`.
+    fn improves(&self, cell: (u32, u32), distance: u32) -> bool {
+        match self.distance(cell) {
+            Some(known) => distance < known,
+            None => true,
+        }
+    }
+
+    /// Update the distances after the cells in `changed` became passable or impassable.
+    ///
+    /// Only the cells whose distance could have changed are revisited, so this is much cheaper
+    /// than building a new field when a single tower is placed or removed.
+    pub fn update(&mut self, grid: &Grid, mode: NavMode, changed: &[(u32, u32)]) {
+        let goal = self.goal;
+        let passable = |cell: (u32, u32)| cell == goal || mode.is_passable(grid, cell.0, cell.1);
+
+        // Forget the distances of cells that became impassable, along with every cell whose
+        // shortest route went through one of them.
+        let mut open = VecDeque::new();
+        for &cell in changed {
+            if !passable(cell) {
+                if let Some(distance) = self.distance(cell) {
+                    self.clear_distance(cell);
+                    open.push_back((cell, distance));
+                }
+            }
+        }
+        let mut forgotten = Vec::new();
+        while let Some((cell, distance)) = open.pop_front() {
+            for neighbor in neighbors(grid, cell) {
+                if self.distance(neighbor) != Some(distance + 1) {
+                    continue;
+                }
+                // The neighbor keeps its distance if it has another way to the goal that's as short.
+                if !neighbors(grid, neighbor).any(|other| self.distance(other) == Some(distance)) {
+                    self.clear_distance(neighbor);
+                    forgotten.push(neighbor);
+                    open.push_back((neighbor, distance + 1));
+                }
+            }
+        }
+
+        // Spread distances back out from the cells that kept theirs into the forgotten cells and
+        // any cells that became passable, shortest first.
+        let mut heap = BinaryHeap::new();
+        for &cell in forgotten.iter().chain(changed) {
+            if cell == goal || !passable(cell) {
+                continue;
+            }
+            if let Some(distance) = neighbors(grid, cell).filter_map(|neighbor| self.distance(neighbor)).min() {
+                heap.push(Reverse((distance + 1, cell)));
+            }
+        }
+        while let Some(Reverse((distance, cell))) = heap.pop() {
+            if !self.improves(cell, distance) {
+                continue;
+            }
+            self.set_distance(cell, distance);
+            for neighbor in neighbors(grid, cell) {
+                if passable(neighbor) && self.improves(neighbor, distance + 1) {
+                    heap.push(Reverse((distance + 1, neighbor)));
+                }
+            }
+        }
+    }
+
+    /// The neighbor of `cell` to step to next to get closer to the goal.
+    ///
+    /// Works from impassable cells too, so enemies can leave the spawner or waypoint they're on.
+    pub fn next_cell(&self, grid: &Grid, cell: (u32, u32)) -> Option<(u32, u32)> {
+        let current = self.distance(cell).unwrap_or(u32::MAX);
+        neighbors(grid, cell)
+            .filter_map(|neighbor| self.distance(neighbor).map(|distance| (distance, neighbor)))
+            .filter(|&(distance, _)| distance < current)
+            .min()
+            .map(|(_, neighbor)| neighbor)
+    }
+
+    /// Whether an enemy on `cell` can reach the goal.
+    pub fn reaches(&self, grid: &Grid, cell: (u32, u32)) -> bool {
+        cell == self.goal || self.next_cell(grid, cell).is_some()
+    }
+}
+
+/// A start cell and a goal cell enemies need to walk between.
+pub type Leg = ((u32, u32), (u32, u32));
+
+/// The grid as it would be with one more cell occupied, with flow fields toward the goals of some
+/// legs, to check whether enemies could still get everywhere they need to go.
+#[derive(Clone, Debug)]
+pub struct BlockedGrid {
+    grid: Grid,
+    mode: NavMode,
+    fields: HashMap<(u32, u32), FlowField>,
+}
+
+impl BlockedGrid {
+    pub fn new(grid: &Grid, mode: NavMode, cell: (u32, u32), legs: &[Leg]) -> Self {
+        let mut grid = grid.clone();
+        grid.set_cell(cell.0, cell.1, GridCell::Occupied);
+
+        // Only build one flow field per goal.
+        let mut fields = HashMap::new();
+        for &(_, goal) in legs {
+            fields.entry(goal).or_insert_with(|| FlowField::new(&grid, mode, goal));
+        }
+        Self {
+            grid,
+            mode,
+            fields,
+        }
+    }
+
+    /// Check that enemies could still walk from the start to the goal of every leg.
+    pub fn keeps_connected(&self, legs: &[Leg]) -> bool {
+        legs.iter().all(|&(start, goal)| match self.fields.get(&goal) {
+            Some(field) => field.reaches(&self.grid, start),
+            None => FlowField::new(&self.grid, self.mode, goal).reaches(&self.grid, start),
+        })
+    }
+}
+
+/// A waypoint enemies can walk to.
+#[derive(Clone, Copy, Debug)]
+pub struct NavWaypoint {
+    pub id: u8,
+    pub entity: Entity,
+    pub cell: (u32, u32),
+}
+
+/// A named path of waypoints enemies walk through in order of id.
+///
+/// After the last waypoint, enemies continue onto one of the branches, picked by weight. Paths
+/// without branches end at a base.
+#[derive(Clone, Debug, Default)]
+pub struct NavPath {
+    pub waypoints: Vec<NavWaypoint>,
+    /// Names of the paths enemies can continue onto, with their weights.
+    pub branches: Vec<(String, u32)>,
+}
+
+impl NavPath {
+    pub fn first_waypoint(&self) -> Option<NavWaypoint> {
+        self.waypoints.first().cloned()
+    }
+}
+
+/// The level's paths, plus flow fields toward every waypoint shared by all enemies.
+///
+/// Build it once per level, then keep it up to date by passing it the cells that change on the
+/// grid.
+#[derive(Clone, Debug, Default)]
+pub struct Navigation {
+    pub mode: NavMode,
+    paths: HashMap<String, NavPath>,
+    fields: HashMap<(u32, u32), FlowField>,
+    /// Steps from each waypoint to the end of its route, by path and then waypoint id.
+    route_distances: HashMap<String, HashMap<u8, u32>>,
+}
+
+impl Navigation {
+    pub fn new(grid: &Grid, mode: NavMode, mut paths: HashMap<String, NavPath>) -> Self {
+        for path in paths.values_mut() {
+            path.waypoints.sort_by_key(|waypoint| waypoint.id);
+        }
+        let fields = paths.values()
+            .flat_map(|path| &path.waypoints)
+            .map(|waypoint| (waypoint.cell, FlowField::new(grid, mode, waypoint.cell)))
+            .collect();
+        let mut navigation = Self {
+            mode,
+            paths,
+            fields,
+            route_distances: HashMap::new(),
+        };
+        navigation.measure_routes(grid);
+        navigation
+    }
+
+    /// Look up a waypoint by the path it's on and its id.
+    pub fn waypoint(&self, path: &str, id: u8) -> Option<NavWaypoint> {
+        self.paths.get(path)?
+            .waypoints.iter()
+            .find(|waypoint| waypoint.id == id)
+            .cloned()
+    }
+
+    /// Pick the waypoint to head to after waypoint `id` on `path`, taking a branch by weight at
+    /// the end of the path. Returns `None` at the end of the route.
+    pub fn next_waypoint<R: Rng>(&self, path: &str, id: u8, rng: &mut R) -> Option<NavWaypoint> {
+        let nav_path = self.paths.get(path)?;
+        if let Some(waypoint) = nav_path.waypoints.iter().find(|waypoint| waypoint.id > id) {
+            return Some(*waypoint);
+        }
+
+        let total_weight: u32 = nav_path.branches.iter().map(|(_, weight)| weight).sum();
+        if total_weight == 0 {
+            return None;
+        }
+        let mut roll = rng.gen_range(0, total_weight);
+        for (branch, weight) in &nav_path.branches {
+            if roll < *weight {
+                return self.paths.get(branch)?.first_waypoint();
+            }
+            roll -= weight;
+        }
+        None
+    }
+
+    /// Get every leg enemies walk between waypoints, including onto each branch.
+    pub fn legs(&self) -> Vec<Leg> {
+        let mut legs = Vec::new();
+        for path in self.paths.values() {
+            for pair in path.waypoints.windows(2) {
+                legs.push((pair[0].cell, pair[1].cell));
+            }
+            if let Some(last) = path.waypoints.last() {
+                for (branch, _) in &path.branches {
+                    if let Some(first) = self.paths.get(branch).and_then(NavPath::first_waypoint) {
+                        legs.push((last.cell, first.cell));
+                    }
+                }
+            }
+        }
+        legs
+    }
+
+    /// Get the flow field toward the waypoint in `goal`.
+    pub fn field(&self, goal: (u32, u32)) -> Option<&FlowField> {
+        self.fields.get(&goal)
+    }
+
+    /// Update every flow field after the cells in `changed` change on the grid.
+    pub fn update(&mut self, grid: &Grid, changed: &[(u32, u32)]) {
+        if changed.is_empty() {
+            return;
+        }
+        for field in self.fields.values_mut() {
+            field.update(grid, self.mode, changed);
+        }
+        self.measure_routes(grid);
+    }
+
+    /// Steps from waypoint `id` on `path` to the end of its route, taking the shortest branches.
+    /// Returns `None` if the end can't be reached.
+    pub fn route_distance(&self, path: &str, id: u8) -> Option<u32> {
+        self.route_distances.get(path)?.get(&id).cloned()
+    }
+
+    fn measure_routes(&mut self, grid: &Grid) {
+        let mut known = HashMap::new();
+        for (name, path) in &self.paths {
+            for waypoint in &path.waypoints {
+                self.measure_route(grid, name, waypoint.id, &mut known, &mut HashSet::new());
+            }
+        }
+        self.route_distances.clear();
+        for ((path, id), distance) in known {
+            if let Some(distance) = distance {
+                self.route_distances.entry(path).or_default().insert(id, distance);
+            }
+        }
+    }
+
+    /// Measure the route from a waypoint to its end, remembering every waypoint measured along
+    /// the way in `known`. Routes that loop back to a waypoint in `visiting` never end.
+    fn measure_route(
+        &self,
+        grid: &Grid,
+        path: &str,
+        id: u8,
+        known: &mut HashMap<(String, u8), Option<u32>>,
+        visiting: &mut HashSet<(String, u8)>,
+    ) -> Option<u32> {
+        let key = (path.to_owned(), id);
+        if let Some(&distance) = known.get(&key) {
+            return distance;
+        }
+        let nav_path = self.paths.get(path)?;
+        let waypoint = nav_path.waypoints.iter().find(|waypoint| waypoint.id == id)?;
+        if !visiting.insert(key.clone()) {
+            return None;
+        }
+
+        // Head to the next waypoint on the path, or onto any of the branches at its end.
+        let next: Vec<(&str, NavWaypoint)> = match nav_path.waypoints.iter().find(|next| next.id > id) {
+            Some(next) => vec![(path, *next)],
+            None => nav_path.branches.iter()
+                .filter_map(|(branch, _)| Some((branch.as_str(), self.paths.get(branch)?.first_waypoint()?)))
+                .collect(),
+        };
+        let distance = if next.is_empty() {
+            Some(0)
+        } else {
+            next.into_iter()
+                .filter_map(|(next_path, next)| {
+                    let leg = self.fields.get(&next.cell)?.distance_from(grid, waypoint.cell)?;
+                    Some(leg + self.measure_route(grid, next_path, next.id, known, visiting)?)
+                })
+                .min()
+        };
+
+        visiting.remove(&key);
+        known.insert(key, distance);
+        distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn assert_same_distances(grid: &Grid, a: &FlowField, b: &FlowField) {
+        for y in 0..grid.height {
+            for x in 0..grid.width {
+                assert_eq!(a.distance((x, y)), b.distance((x, y)), "Distances differ at {:?}", (x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn update_matches_rebuilding_the_field() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for &mode in &[NavMode::Paths, NavMode::Maze] {
+            for _ in 0..200 {
+                let mut grid = Grid::new(12, 9, 1.0);
+                for cell in grid.grid.iter_mut() {
+                    *cell = match rng.gen_range(0, 3) {
+                        0 => GridCell::Walkable,
+                        1 => GridCell::Buildable,
+                        _ => GridCell::Occupied,
+                    };
+                }
+                let goal = (rng.gen_range(0, grid.width), rng.gen_range(0, grid.height));
+                let mut field = FlowField::new(&grid, mode, goal);
+                grid.take_changes();
+
+                // Build and remove towers a few cells at a time, like a player would.
+                for _ in 0..10 {
+                    for _ in 0..rng.gen_range(1, 4) {
+                        let (x, y) = (rng.gen_range(0, grid.width), rng.gen_range(0, grid.height));
+                        let cell = match rng.gen_range(0, 3) {
+                            0 => GridCell::Walkable,
+                            1 => GridCell::Buildable,
+                            _ => GridCell::Occupied,
+                        };
+                        grid.set_cell(x, y, cell);
+                    }
+                    let changed = grid.take_changes();
+                    field.update(&grid, mode, &changed);
+                    assert_same_distances(&grid, &field, &FlowField::new(&grid, mode, goal));
+                }
+            }
+        }
+    }
+}
@@ -1,5 +1,9 @@
+use rand::{rngs::SmallRng, SeedableRng};
 use specs::Entity;
 
+use crate::data::WaveSchedule;
+use crate::pathfinding::NavMode;
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct DeltaTime(pub f32);
 
@@ -21,6 +25,85 @@ pub struct BuildResources {
     pub bits: u32,
 }
 
+/// Economy and balance settings for the current level, read from the map's custom properties.
+#[derive(Clone, Copy, Debug)]
+pub struct LevelSettings {
+    pub starting_bits: u32,
+    /// Scales the bounty of every enemy killed.
+    pub bounty_multiplier: f32,
+    pub base_hp: u32,
+    /// Fraction of unspent bits awarded at the start of each wave after the first.
+    pub interest_rate: f32,
+    /// Fraction of the bits spent on a tower, upgrades included, given back when it's sold.
+    pub sell_refund: f32,
+    pub nav_mode: NavMode,
+}
+
+impl Default for LevelSettings {
+    fn default() -> Self {
+        Self {
+            starting_bits: 30,
+            bounty_multiplier: 1.0,
+            base_hp: 1,
+            interest_rate: 0.0,
+            sell_refund: 0.75,
+            nav_mode: NavMode::Paths,
+        }
+    }
+}
+
+/// Progress through the level's `WaveSchedule`.
+#[derive(Clone, Debug, Default)]
+pub struct Waves {
+    pub schedule: WaveSchedule,
+    /// Index of the next wave to start.
+    pub next_wave: usize,
+    /// Seconds until the next wave starts.
+    pub countdown: f32,
+    /// Set to start the next wave without waiting for the countdown.
+    pub call_early: bool,
+}
+
+impl Waves {
+    pub fn new(schedule: WaveSchedule) -> Self {
+        Self {
+            countdown: schedule.first_countdown,
+            schedule,
+            next_wave: 0,
+            call_early: false,
+        }
+    }
+
+    /// Whether every wave has been started.
+    pub fn is_finished(&self) -> bool {
+        self.next_wave >= self.schedule.waves.len()
+    }
+
+    pub fn call_next_wave(&mut self) {
+        if !self.is_finished() {
+            self.call_early = true;
+        }
+    }
+}
+
+/// Random numbers for gameplay, like which branch of a path an enemy takes.
+///
+/// Seeded when a level loads, so headless runs of a level always play out the same way.
+#[derive(Clone, Debug)]
+pub struct Random(pub SmallRng);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self(SmallRng::seed_from_u64(seed))
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
 pub struct CollisionEvent {
     pub entity_a: Entity,
     pub entity_b: Entity,
@@ -29,3 +112,16 @@ pub struct CollisionEvent {
 pub struct DeathEvent {
     pub entity: Entity,
 }
+
+/// An attacker hit a target directly, without colliding with it, like a beam tower firing.
+pub struct HitEvent {
+    pub attacker: Entity,
+    pub target: Entity,
+}
+
+/// An enemy reached the end of its route.
+pub struct ReachedGoalEvent {
+    pub enemy: Entity,
+    /// The waypoint at the end of the route, usually a `Base`.
+    pub goal: Entity,
+}
@@ -0,0 +1,110 @@
+use specs::prelude::*;
+
+use crate::components::*;
+use crate::level::{self, LevelError};
+use crate::resources::*;
+use crate::systems::*;
+
+/// The game logic without any windowing, input, or rendering.
+///
+/// Owns the specs `World` and the `Dispatcher` that runs all gameplay systems. Can be stepped
+/// with a fixed time step, so whole matches can be run headless.
+pub struct Simulation<'a, 'b> {
+    pub world: World,
+    dispatcher: Dispatcher<'a, 'b>,
+}
+
+impl<'a, 'b> Simulation<'a, 'b> {
+    pub fn new() -> Self {
+        // Set up the specs world.
+        let mut world = World::new();
+        world.register::<Transform>();
+        world.register::<Drawable>();
+        // Beam segments are only read when drawing, and towers only by player input, so no
+        // system registers them.
+        world.register::<BeamSegment>();
+        world.register::<Tower>();
+
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(NavigationSystem, "navigation_system", &[])
+            .with(EnemyAi, "enemy_ai", &["navigation_system"])
+            .with(ShooterSystem, "shooter_system", &["enemy_ai"])
+            .with(HomingSystem, "homing_system", &["shooter_system"])
+            .with(UpdatePosition, "update_position", &["homing_system"])
+            .with(CleanupSystem, "cleanup_system", &["update_position"])
+            .with(CollisionSystem, "collision_system", &["cleanup_system"])
+            .with(AttackSystem, "attack_system", &["collision_system"])
+            .with(StatusSystem, "status_system", &["attack_system"])
+            .with(RegenSystem, "regen_system", &["status_system"])
+            .with(GoalSystem, "goal_system", &["regen_system"])
+            .with(WaveSystem, "wave_system", &["goal_system"])
+            .with(SpawnerSystem, "spawner_system", &["wave_system"])
+            .with(DeathSystem, "death_system", &["spawner_system"])
+            .with(WinSystem, "win_system", &["death_system"])
+            .build();
+
+        dispatcher.setup(&mut world);
+
+        Self {
+            world,
+            dispatcher,
+        }
+    }
+
+    /// Load a level, keeping the current one if it fails to load.
+    pub fn load_level(&mut self, level_name: &str) -> Result<(), LevelError> {
+        level::load_level(level_name, &mut self.world)
+    }
+
+    /// Advance the simulation by `dt` seconds.
+    ///
+    /// Does nothing but apply pending entity changes once the level has been won or lost.
+    pub fn step(&mut self, dt: f32) {
+        // Update all entities created outside of systems, e.g. via input events.
+        self.world.maintain();
+
+        {
+            // Sets the time and updates it
+            self.world.insert(DeltaTime(dt));
+
+            // Clears collision event vector
+            let mut collisions = self.world.write_resource::<Vec<CollisionEvent>>();
+            collisions.clear();
+            let mut hit_events = self.world.write_resource::<Vec<HitEvent>>();
+            hit_events.clear();
+            let mut death_events = self.world.write_resource::<Vec<DeathEvent>>();
+            death_events.clear();
+            let mut reached_goal_events = self.world.write_resource::<Vec<ReachedGoalEvent>>();
+            reached_goal_events.clear();
+        }
+
+        if self.play_state() == PlayState::Play {
+            self.dispatcher.dispatch(&self.world);
+
+            // Update all entities created/deleted in systems.
+            self.world.maintain();
+        }
+    }
+
+    /// Step the simulation with a fixed `dt` until the level is won or lost, or until `max_steps`
+    /// steps have run. Returns the final `PlayState`.
+    pub fn run(&mut self, dt: f32, max_steps: u32) -> PlayState {
+        for _ in 0..max_steps {
+            if self.play_state() != PlayState::Play {
+                break;
+            }
+            self.step(dt);
+        }
+        self.play_state()
+    }
+
+    pub fn play_state(&self) -> PlayState {
+        *self.world.read_resource::<PlayState>()
+    }
+}
+
+impl<'a, 'b> Default for Simulation<'a, 'b> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
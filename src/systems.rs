@@ -1,10 +1,35 @@
-use ggez::nalgebra;
+use std::collections::HashSet;
+
+use ggez::nalgebra::{self, Point2, Vector2};
 use specs::prelude::*;
 
 use crate::components::*;
+use crate::damage::{final_damage, resist};
+use crate::data::*;
+use crate::grid::*;
+use crate::pathfinding::Navigation;
 use crate::rect::*;
 use crate::resources::*;
 
+/// Keeps the flow fields in `Navigation` in step with the cells that change on the `Grid`.
+pub struct NavigationSystem;
+
+impl<'a> System<'a> for NavigationSystem {
+    type SystemData = (
+        Write<'a, Grid>,
+        Write<'a, Navigation>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut grid, mut navigation) = data;
+
+        let changes = grid.take_changes();
+        if !changes.is_empty() {
+            navigation.update(&grid, &changes);
+        }
+    }
+}
+
 pub struct UpdatePosition;
 
 impl<'a> System<'a> for UpdatePosition {
@@ -23,6 +48,95 @@ impl<'a> System<'a> for UpdatePosition {
     }
 }
 
+/// Seconds before a projectile that hasn't hit anything is removed.
+const PROJECTILE_LIFETIME: f32 = 5.0;
+/// How far projectiles fly before they're removed, as a multiple of the tower's attack radius.
+const PROJECTILE_RANGE: f32 = 2.0;
+
+/// Seconds a beam stays on screen after it's fired.
+const BEAM_LIFETIME: f32 = 0.1;
+
+/// Seconds between attacks for melee enemies at their goal.
+const MELEE_SECONDS_PER_ATTACK: f32 = 1.0;
+
+/// Score a target in range of a tower, so the tower shoots the one with the highest score.
+fn target_score(mode: TargetingMode, distance: f32, enemy: Option<&Enemy>, health: Option<&Health>) -> f32 {
+    let distance_left = enemy.map_or(f32::MAX, |enemy| enemy.distance_left);
+    let hp = health.map_or(0, |health| health.current_hp) as f32;
+    match mode {
+        TargetingMode::First => -distance_left,
+        TargetingMode::Last => distance_left,
+        TargetingMode::Closest => -distance,
+        TargetingMode::Strongest => hp,
+        TargetingMode::Weakest => -hp,
+    }
+}
+
+/// Get where to aim so a projectile fired from `from` at `speed` meets a target at `target`
+/// moving with `target_velocity`. Aims straight at the target if the projectile can't catch it.
+fn lead_target(from: Point2<f32>, target: Point2<f32>, target_velocity: Vector2<f32>, speed: f32) -> Point2<f32> {
+    // Solve |offset + target_velocity * t| = speed * t for the time t the projectile hits.
+    let offset = target - from;
+    let a = target_velocity.dot(&target_velocity) - speed * speed;
+    let b = 2.0 * offset.dot(&target_velocity);
+    let c = offset.dot(&offset);
+    let time = if a.abs() < f32::EPSILON {
+        -c / b
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return target;
+        }
+        let root = discriminant.sqrt();
+        let (t1, t2) = ((-b - root) / (2.0 * a), (-b + root) / (2.0 * a));
+        if t1 > 0.0 && (t1 < t2 || t2 <= 0.0) { t1 } else { t2 }
+    };
+    if time.is_finite() && time > 0.0 {
+        target + target_velocity * time
+    } else {
+        target
+    }
+}
+
+/// Removes entities whose `Lifetime` has run out, and projectiles that have left the grid.
+pub struct CleanupSystem;
+
+impl<'a> System<'a> for CleanupSystem {
+    type SystemData = (
+        Read<'a, DeltaTime>,
+        Read<'a, Grid>,
+        Entities<'a>,
+        WriteStorage<'a, Lifetime>,
+        ReadStorage<'a, Projectile>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Velocity>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (dt, grid, entities, mut lifetimes, projectiles, transforms, velocities) = data;
+
+        for (entity, lifetime, velocity) in (&entities, &mut lifetimes, velocities.maybe()).join() {
+            lifetime.seconds -= dt.0;
+            if let Some(velocity) = velocity {
+                lifetime.distance -= velocity.0.magnitude() * dt.0;
+            }
+            if lifetime.seconds <= 0.0 || lifetime.distance <= 0.0 {
+                if let Err(e) = entities.delete(entity) {
+                    println!("Entity could not be deleted {}", e);
+                }
+            }
+        }
+
+        for (entity, _, transform) in (&entities, &projectiles, &transforms).join() {
+            if grid.cell_at(transform.position.x, transform.position.y).is_none() {
+                if let Err(e) = entities.delete(entity) {
+                    println!("Entity could not be deleted {}", e);
+                }
+            }
+        }
+    }
+}
+
 pub struct ShooterSystem;
 
 impl<'a> System<'a> for ShooterSystem {
@@ -33,38 +147,182 @@ impl<'a> System<'a> for ShooterSystem {
         ReadStorage<'a, Transform>,
         WriteStorage<'a, Shooter>,
         ReadStorage<'a, Faction>,
+        ReadStorage<'a, Attacker>,
+        ReadStorage<'a, Enemy>,
+        ReadStorage<'a, Health>,
+        ReadStorage<'a, Velocity>,
+        ReadStorage<'a, AreaDamage>,
+        ReadStorage<'a, Pierce>,
+        ReadStorage<'a, BeamAttack>,
+        ReadStorage<'a, InflictsEffects>,
+        Write<'a, Vec<HitEvent>>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (dt, ent, lazy, transforms, mut shooters, factions) = data;
-
-        for (transform, shooter,  faction) in (&transforms, &mut shooters, &factions).join() {
+        let (
+            dt,
+            ent,
+            lazy,
+            transforms,
+            mut shooters,
+            factions,
+            attackers,
+            enemies,
+            healths,
+            velocities,
+            area_damages,
+            pierces,
+            beam_attacks,
+            inflicts_effects,
+            mut hit_events,
+        ) = data;
+
+        for (tower, transform, shooter, faction, attacker, area_damage, pierce, beam_attack, inflicts) in (
+            &ent,
+            &transforms,
+            &mut shooters,
+            &factions,
+            &attackers,
+            area_damages.maybe(),
+            pierces.maybe(),
+            beam_attacks.maybe(),
+            inflicts_effects.maybe(),
+        ).join() {
             if shooter.cooldown > 0.0 {
                 shooter.cooldown -= dt.0;
             } else {
-                for (target_transform, target_fraction) in (&transforms, &factions).join() {
+                // Find the target in range that best fits the tower's targeting mode.
+                let mut best_target: Option<(f32, Entity, &Transform)> = None;
+                for (target, target_transform, target_fraction) in (&ent, &transforms, &factions).join() {
                     if target_fraction != faction {
                         // Determine if enemy is within range of the tower
                         let distance = nalgebra::distance(&transform.position, &target_transform.position);
                         if distance <= shooter.attack_radius {
-                            shooter.cooldown = shooter.seconds_per_attack;
-                            // Spawning the projectile
-                            let projectile = ent.create();
-                            lazy.insert(projectile, Projectile{});
-                            lazy.insert(projectile, *transform);
-                            lazy.insert(projectile, Drawable::Projectile);
-                            lazy.insert(projectile, *faction);
-
-                            let direction = (target_transform.position - transform.position).normalize();
-                            let velocity = direction * 300.0;
-                            lazy.insert(projectile, Velocity(velocity));
-
-                            lazy.insert(projectile, Collider::new(8.0, 8.0));
-                            lazy.insert(projectile, Attacker {damage: 1});
+                            let score = target_score(shooter.targeting, distance, enemies.get(target), healths.get(target));
+                            let better = match best_target {
+                                Some((best_score, _, _)) => score > best_score,
+                                None => true,
+                            };
+                            if better {
+                                best_target = Some((score, target, target_transform));
+                            }
+                        }
+                    }
+                }
+
+                if let (Some((_, target, target_transform)), Some(beam_attack)) = (best_target, beam_attack) {
+                    shooter.cooldown = shooter.seconds_per_attack;
+                    // Beams hit instantly, then jump to the closest enemy that hasn't been hit yet.
+                    let mut hit = vec![target];
+                    let mut start = transform.position;
+                    let mut end = target_transform.position;
+                    loop {
+                        hit_events.push(HitEvent {
+                            attacker: tower,
+                            target: hit[hit.len() - 1],
+                        });
+                        let segment = ent.create();
+                        lazy.insert(segment, Transform { position: start });
+                        lazy.insert(segment, BeamSegment { end });
+                        lazy.insert(segment, Drawable::Beam);
+                        lazy.insert(segment, Lifetime::new(BEAM_LIFETIME, f32::INFINITY));
+
+                        if hit.len() > beam_attack.chain as usize {
                             break;
                         }
+                        let mut next: Option<(f32, Entity, Point2<f32>)> = None;
+                        for (other, other_transform, other_faction) in (&ent, &transforms, &factions).join() {
+                            let distance = nalgebra::distance(&end, &other_transform.position);
+                            let closer = match next {
+                                Some((next_distance, _, _)) => distance < next_distance,
+                                None => true,
+                            };
+                            if other_faction != faction && !hit.contains(&other) && distance <= beam_attack.chain_radius && closer {
+                                next = Some((distance, other, other_transform.position));
+                            }
+                        }
+                        match next {
+                            Some((_, other, position)) => {
+                                hit.push(other);
+                                start = end;
+                                end = position;
+                            }
+                            None => break,
+                        }
+                    }
+                } else if let Some((_, target, target_transform)) = best_target {
+                    shooter.cooldown = shooter.seconds_per_attack;
+                    // Spawning the projectile
+                    let projectile = ent.create();
+                    lazy.insert(projectile, Projectile{});
+                    lazy.insert(projectile, *transform);
+                    lazy.insert(projectile, Drawable::Projectile);
+                    lazy.insert(projectile, *faction);
+
+                    let aim_point = match (shooter.aim, velocities.get(target)) {
+                        (AimMode::Lead, Some(target_velocity)) => lead_target(
+                            transform.position,
+                            target_transform.position,
+                            target_velocity.0,
+                            shooter.projectile_speed,
+                        ),
+                        _ => target_transform.position,
+                    };
+                    let direction = (aim_point - transform.position).normalize();
+                    let velocity = direction * shooter.projectile_speed;
+                    lazy.insert(projectile, Velocity(velocity));
+                    if shooter.aim == AimMode::Homing {
+                        lazy.insert(projectile, Homing { target });
+                    }
+
+                    lazy.insert(projectile, Collider::new(8.0, 8.0));
+                    lazy.insert(projectile, Lifetime::new(PROJECTILE_LIFETIME, shooter.attack_radius * PROJECTILE_RANGE));
+                    // Projectiles hit as hard as the tower that fired them.
+                    lazy.insert(projectile, attacker.clone());
+                    if let Some(area_damage) = area_damage {
+                        lazy.insert(projectile, area_damage.clone());
                     }
+                    if let Some(pierce) = pierce {
+                        lazy.insert(projectile, Pierce::new(pierce.remaining));
+                    }
+                    if let Some(inflicts) = inflicts {
+                        lazy.insert(projectile, inflicts.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Steers homing projectiles toward their targets, removing them once their target is gone.
+pub struct HomingSystem;
+
+impl<'a> System<'a> for HomingSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Homing>,
+        ReadStorage<'a, Transform>,
+        WriteStorage<'a, Velocity>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, homings, transforms, mut velocities) = data;
+
+        for (entity, homing, transform, velocity) in (&entities, &homings, &transforms, &mut velocities).join() {
+            let target_transform = match transforms.get(homing.target) {
+                Some(target_transform) => target_transform,
+                None => {
+                    if let Err(e) = entities.delete(entity) {
+                        println!("Entity could not be deleted {}", e);
+                    }
+                    continue;
                 }
+            };
+
+            // Keep the same speed, just turn toward the target.
+            let offset = target_transform.position - transform.position;
+            if offset.magnitude() > 0.0 {
+                velocity.0 = velocity.0.magnitude() * offset.normalize();
             }
         }
     }
@@ -111,6 +369,21 @@ impl<'a> System<'a> for CollisionSystem {
     }
 }
 
+/// Take `damage` off an entity's health, removing the entity if that kills it.
+fn apply_damage(entities: &Entities, entity: Entity, health: &mut Health, damage: u32, death_events: &mut Vec<DeathEvent>) {
+    health.take_damage(damage);
+
+    if health.current_hp == 0 {
+        if let Err(e) = entities.delete(entity) {
+            println!("Entity could not be deleted {}", e);
+        } else {
+            death_events.push(DeathEvent {
+                entity
+            });
+        }
+    }
+}
+
 pub struct AttackSystem;
 
 impl<'a> System<'a> for AttackSystem {
@@ -118,35 +391,266 @@ impl<'a> System<'a> for AttackSystem {
         Read<'a, Vec<CollisionEvent>>,
         Entities<'a>,
         ReadStorage<'a, Attacker>,
+        ReadStorage<'a, AreaDamage>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Faction>,
+        WriteStorage<'a, Pierce>,
         WriteStorage<'a, Health>,
+        ReadStorage<'a, Armor>,
+        ReadStorage<'a, Resistances>,
+        ReadStorage<'a, InflictsEffects>,
+        WriteStorage<'a, StatusEffects>,
+        Read<'a, Vec<HitEvent>>,
         Write<'a, Vec<DeathEvent>>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (collision_events, entities, attackers, mut healths, mut death_events) = data;
-
-        for event in collision_events.iter() {
-            let attacker = attackers.get(event.entity_a);
-            let health = healths.get_mut(event.entity_b);
+        let (
+            collision_events,
+            entities,
+            attackers,
+            area_damages,
+            transforms,
+            factions,
+            mut pierces,
+            mut healths,
+            armors,
+            resistances,
+            inflicts_effects,
+            mut status_effects,
+            hit_events,
+            mut death_events,
+        ) = data;
+
+        // Attackers that were used up this frame, so they don't hit anything else.
+        let mut spent = HashSet::new();
+
+        // Attackers hit what they collide with and are used up by it, while direct hits like beams
+        // leave the attacker as is.
+        let collisions = collision_events.iter().map(|event| (event.entity_a, event.entity_b, true));
+        let direct_hits = hit_events.iter().map(|event| (event.attacker, event.target, false));
+        for (attacker_entity, target, collided) in collisions.chain(direct_hits) {
+            if spent.contains(&attacker_entity) {
+                continue;
+            }
+            let attacker = match attackers.get(attacker_entity) {
+                Some(attacker) => attacker,
+                None => continue,
+            };
+            // Skip targets that can't be hurt, or were already killed this frame.
+            match healths.get(target) {
+                Some(health) if health.current_hp > 0 => {}
+                _ => continue,
+            }
+            // Piercing attackers pass through targets they've already hit.
+            if let Some(pierce) = pierces.get(attacker_entity) {
+                if collided && pierce.hit.contains(&target) {
+                    continue;
+                }
+            }
 
-            if let (Some(attacker), Some(health)) = (attacker, health) {
-                health.current_hp = health.current_hp.saturating_sub(attacker.damage);
+            // Area attacks hurt everything near where they hit as well as the target.
+            let mut hits = vec![(target, attacker.damage)];
+            let center = if collided {
+                transforms.get(attacker_entity)
+            } else {
+                transforms.get(target)
+            };
+            let area = (area_damages.get(attacker_entity), center, factions.get(attacker_entity));
+            if let (Some(area_damage), Some(center), Some(faction)) = area {
+                for (other, other_transform, other_faction, _) in (&entities, &transforms, &factions, &healths).join() {
+                    let distance = nalgebra::distance(&center.position, &other_transform.position);
+                    if other != target && other_faction != faction && distance <= area_damage.radius {
+                        hits.push((other, area_damage.damage_at(attacker.damage, distance)));
+                    }
+                }
+            }
+            for (hit_entity, damage) in hits {
+                let health = match healths.get_mut(hit_entity) {
+                    Some(health) if health.current_hp > 0 => health,
+                    _ => continue,
+                };
+                // Shredding can take armor off, but never below nothing.
+                let armor = armors.get(hit_entity).map_or(0, |armor| armor.value);
+                let shred = status_effects.get(hit_entity).map_or(0, StatusEffects::armor_shred);
+                let damage = final_damage(damage, attacker.damage_type, armor.saturating_sub(shred), resistances.get(hit_entity));
+                apply_damage(&entities, hit_entity, health, damage, &mut death_events);
+
+                // Only the survivors are left to be slowed, poisoned and so on.
+                if health.current_hp == 0 {
+                    continue;
+                }
+                if let Some(inflicts) = inflicts_effects.get(attacker_entity) {
+                    if let Ok(entry) = status_effects.entry(hit_entity) {
+                        let status = entry.or_insert_with(StatusEffects::default);
+                        for effect in &inflicts.0 {
+                            status.apply(effect.clone());
+                        }
+                    }
+                }
+            }
 
-                // TODO: Don't delete all attackers
-                if let Err(e) = entities.delete(event.entity_a) {
+            if !collided {
+                continue;
+            }
+            // Attackers are used up after one hit, unless they have pierce left.
+            let used_up = match pierces.get_mut(attacker_entity) {
+                Some(pierce) => {
+                    pierce.hit.push(target);
+                    pierce.remaining = pierce.remaining.saturating_sub(1);
+                    pierce.remaining == 0
+                }
+                None => true,
+            };
+            if used_up {
+                spent.insert(attacker_entity);
+                if let Err(e) = entities.delete(attacker_entity) {
                     println!("Entity could not be deleted {}", e);
                 } else {
                     death_events.push(DeathEvent {
-                        entity: event.entity_a
+                        entity: attacker_entity
                     });
                 }
+            }
+        }
+    }
+}
+
+/// Ticks down status effects, dealing damage over time and removing effects that ran out.
+pub struct StatusSystem;
+
+impl<'a> System<'a> for StatusSystem {
+    type SystemData = (
+        Read<'a, DeltaTime>,
+        Entities<'a>,
+        WriteStorage<'a, StatusEffects>,
+        ReadStorage<'a, Resistances>,
+        WriteStorage<'a, Health>,
+        Write<'a, Vec<DeathEvent>>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (dt, entities, mut status_effects, resistances, mut healths, mut death_events) = data;
+
+        for (entity, status, resistances) in (&entities, &mut status_effects, resistances.maybe()).join() {
+            // Effects only count for as much of the frame as they had left. Damage over time is
+            // poison damage, so poison resistance cuts it down too.
+            for effect in status.effects.iter_mut() {
+                if effect.kind == StatusKind::DamageOverTime {
+                    let damage = effect.strength * effect.seconds.min(dt.0);
+                    status.pending_damage += resist(damage, DamageType::Poison, resistances);
+                }
+                effect.seconds -= dt.0;
+            }
+            status.effects.retain(|effect| effect.seconds > 0.0);
+
+            // Damage over time ignores armor, and builds up until it adds up to whole HP.
+            let damage = status.pending_damage.floor();
+            if damage >= 1.0 {
+                status.pending_damage -= damage;
+                if let Some(health) = healths.get_mut(entity) {
+                    if health.current_hp > 0 {
+                        apply_damage(&entities, entity, health, damage as u32, &mut death_events);
+                    }
+                }
+            }
+            if status.count(StatusKind::DamageOverTime) == 0 {
+                status.pending_damage = 0.0;
+            }
+        }
+    }
+}
+
+/// Regenerates HP and shields.
+pub struct RegenSystem;
 
+impl<'a> System<'a> for RegenSystem {
+    type SystemData = (
+        Read<'a, DeltaTime>,
+        WriteStorage<'a, Health>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (dt, mut healths) = data;
+
+        for health in (&mut healths).join() {
+            health.regenerate(dt.0);
+        }
+    }
+}
+
+/// Enemies that reach the end of their route damage the base there and leave the level, unless
+/// they have pierce left to keep attacking it with.
+pub struct GoalSystem;
+
+impl<'a> System<'a> for GoalSystem {
+    type SystemData = (
+        Read<'a, DeltaTime>,
+        Read<'a, Vec<ReachedGoalEvent>>,
+        Entities<'a>,
+        ReadStorage<'a, Attacker>,
+        ReadStorage<'a, Base>,
+        WriteStorage<'a, Enemy>,
+        WriteStorage<'a, Pierce>,
+        WriteStorage<'a, Health>,
+        Write<'a, Vec<DeathEvent>>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            dt,
+            reached_goal_events,
+            entities,
+            attackers,
+            bases,
+            mut enemies,
+            mut pierces,
+            mut healths,
+            mut death_events,
+        ) = data;
+
+        for event in reached_goal_events.iter() {
+            // Enemies killed on their way in don't get to hurt the base.
+            if let Some(Health { current_hp: 0, .. }) = healths.get(event.enemy) {
+                continue;
+            }
+
+            // Melee enemies wait at the goal between attacks, using up pierce like projectiles do.
+            let used_up = match (pierces.get_mut(event.enemy), enemies.get_mut(event.enemy)) {
+                (Some(pierce), Some(enemy)) if pierce.remaining > 0 => {
+                    if enemy.attack_cooldown > 0.0 {
+                        enemy.attack_cooldown -= dt.0;
+                        continue;
+                    }
+                    enemy.attack_cooldown = MELEE_SECONDS_PER_ATTACK;
+                    pierce.remaining -= 1;
+                    pierce.remaining == 0
+                }
+                _ => true,
+            };
+            // Leaking enemies aren't killed, so they don't count as deaths or give a bounty.
+            if used_up {
+                if let Err(e) = entities.delete(event.enemy) {
+                    println!("Entity could not be deleted {}", e);
+                    continue;
+                }
+            }
+
+            let damage = attackers.get(event.enemy).map_or(0, |attacker| attacker.damage);
+            if bases.get(event.goal).is_none() {
+                continue;
+            }
+            if let Some(health) = healths.get_mut(event.goal) {
+                if health.current_hp == 0 {
+                    continue;
+                }
+                health.take_damage(damage);
                 if health.current_hp == 0 {
-                    if let Err(e) = entities.delete(event.entity_b) {
+                    if let Err(e) = entities.delete(event.goal) {
                         println!("Entity could not be deleted {}", e);
                     } else {
                         death_events.push(DeathEvent {
-                            entity: event.entity_b
+                            entity: event.goal
                         });
                     }
                 }
@@ -155,56 +659,143 @@ impl<'a> System<'a> for AttackSystem {
     }
 }
 
+pub struct WaveSystem;
+
+impl<'a> System<'a> for WaveSystem {
+    type SystemData = (
+        Read<'a, DeltaTime>,
+        Read<'a, LevelSettings>,
+        Write<'a, Waves>,
+        Write<'a, BuildResources>,
+        WriteStorage<'a, Spawner>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (dt, settings, mut waves, mut build_resources, mut spawners) = data;
+
+        if waves.is_finished() {
+            return;
+        }
+
+        // Only count down once the current wave has finished spawning, unless the player wants
+        // the next wave now.
+        if !waves.call_early {
+            if !(&spawners).join().all(|spawner| spawner.is_idle()) {
+                return;
+            }
+            waves.countdown -= dt.0;
+            if waves.countdown > 0.0 {
+                return;
+            }
+        }
+
+        // Start the next wave by handing each of its groups to their spawners.
+        let wave_index = waves.next_wave;
+        if wave_index > 0 {
+            // Reward the player for saving bits between waves.
+            let interest = (build_resources.bits as f32 * settings.interest_rate) as u32;
+            build_resources.bits += interest;
+        }
+        for group in &waves.schedule.waves[wave_index].groups {
+            let mut sent = false;
+            for spawner in (&mut spawners).join() {
+                let matches = match &group.spawner {
+                    Some(name) => *name == spawner.name,
+                    None => true,
+                };
+                if matches {
+                    spawner.groups.push(SpawnGroup {
+                        enemy: group.enemy.clone(),
+                        count: group.count,
+                        seconds_to_spawn: group.interval,
+                        cooldown: group.delay,
+                    });
+                    sent = true;
+                }
+            }
+            if !sent {
+                println!("Warning: No spawner named \"{}\" for wave {}",
+                         group.spawner.as_ref().map_or("", String::as_str), wave_index + 1);
+            }
+        }
+
+        println!("Wave {} started!", wave_index + 1);
+        waves.next_wave += 1;
+        waves.countdown = waves.schedule.countdown;
+        waves.call_early = false;
+    }
+}
+
 pub struct SpawnerSystem;
 
 impl<'a> System<'a> for SpawnerSystem {
     type SystemData = (
         Read<'a, DeltaTime>,
+        Read<'a, EnemyCatalog>,
+        Read<'a, Navigation>,
         Entities<'a>,
         Read<'a, LazyUpdate>,
         ReadStorage<'a, Transform>,
         WriteStorage<'a, Spawner>,
-        ReadStorage<'a, Waypoint>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (dt, entities, lazy, transforms, mut spawners, waypoints) = data;
-
+        let (dt, enemy_catalog, navigation, entities, lazy, transforms, mut spawners) = data;
+
+        for (transform, spawner) in (&transforms, &mut spawners).join() {
+            // Drop groups that finished spawning last frame.
+            spawner.groups.retain(|group| group.count > 0);
+
+            let spawn_faction = spawner.spawn_faction;
+            let path = &spawner.path;
+            let first_waypoint = spawner.first_waypoint;
+            for group in spawner.groups.iter_mut() {
+                if group.cooldown > 0.0 {
+                    group.cooldown -= dt.0;
+                    continue;
+                }
 
-        for (transform, spawner, spawner_ent) in (&transforms, &mut spawners, &entities).join() {
-            if spawner.cooldown > 0.0 {
-                spawner.cooldown -= dt.0;
-            } else {
-                // Get initial waypoint entity
-                let waypoint_entity = {
-                    let mut entity = None;
-                    for (ent, waypoint) in (&entities, &waypoints).join() {
-                        if waypoint.id == 0 {
-                            entity = Some(ent);
-                        }
-                    }
-                    entity
-                }.expect("Waypoint 0 does not exist");
+                let archetype = if let Some(archetype) = enemy_catalog.get(&group.enemy) {
+                    archetype
+                } else {
+                    println!("Warning: Dropping spawn group of unknown enemy \"{}\"", group.enemy);
+                    group.count = 0;
+                    continue;
+                };
 
+                let waypoint = navigation.waypoint(path, first_waypoint)
+                    .unwrap_or_else(|| panic!("Waypoint {} on path \"{}\" does not exist", first_waypoint, path));
 
-                spawner.cooldown = spawner.seconds_to_spawn;
+                group.cooldown = group.seconds_to_spawn;
                 // Spawn entity
                 let new_ent = entities.create();
-                lazy.insert(new_ent, Enemy { current_waypoint: waypoint_entity});
+                lazy.insert(new_ent, Enemy {
+                    current_waypoint: waypoint.entity,
+                    distance_left: f32::MAX,
+                    speed: archetype.speed,
+                    bounty: archetype.bounty,
+                    attack_cooldown: 0.0,
+                });
                 lazy.insert(new_ent, *transform);
-                lazy.insert(new_ent, spawner.spawn_faction);
-                lazy.insert(new_ent, spawner.spawn_drawable);
-                lazy.insert(new_ent, Velocity::new(60.0, 0.0));
-                lazy.insert(new_ent, Collider::new(40.0, 40.0));
-                lazy.insert(new_ent, Health {current_hp: 5});
-                lazy.insert(new_ent, Attacker {damage:1});
-
-                spawner.count -= 1;
-                if spawner.count == 0 {
-                    if let Err(e) = entities.delete(spawner_ent) {
-                        println!("Entity could not be deleted {}", e);
-                    }
+                lazy.insert(new_ent, spawn_faction);
+                lazy.insert(new_ent, archetype.drawable);
+                lazy.insert(new_ent, Velocity::new(archetype.speed, 0.0));
+                lazy.insert(new_ent, Collider::new(archetype.width, archetype.height));
+                let mut health = Health::new(archetype.hp);
+                health.regen_per_second = archetype.regen_per_second;
+                health.shield = archetype.shield.as_ref()
+                    .map(|shield| Shield::new(shield.hp, shield.regen_per_second, shield.regen_delay));
+                lazy.insert(new_ent, health);
+                lazy.insert(new_ent, Attacker {damage: archetype.damage, damage_type: DamageType::Physical});
+                if archetype.melee {
+                    lazy.insert(new_ent, Pierce::unlimited());
+                }
+                if archetype.armor > 0 {
+                    lazy.insert(new_ent, Armor {value: archetype.armor});
                 }
+                lazy.insert(new_ent, archetype.resistances.clone());
+
+                group.count -= 1;
             }
         }
     }
@@ -214,44 +805,94 @@ pub struct EnemyAi;
 
 impl<'a> System<'a> for EnemyAi {
     type SystemData = (
+        Read<'a, Grid>,
+        Read<'a, Navigation>,
+        Write<'a, Random>,
         Entities<'a>,
         ReadStorage<'a, Waypoint>,
         ReadStorage<'a, Transform>,
+        ReadStorage<'a, StatusEffects>,
         WriteStorage<'a, Enemy>,
         WriteStorage<'a, Velocity>,
+        Write<'a, Vec<ReachedGoalEvent>>,
     );
 
     fn run (&mut self, data: Self::SystemData) {
-        let (entities, waypoints, transforms, mut enemies, mut velocities) = data;
-
-        for (enemy, transform, velocity) in (&mut enemies, &transforms, &mut velocities).join() {
+        let (
+            grid,
+            navigation,
+            mut random,
+            entities,
+            waypoints,
+            transforms,
+            status_effects,
+            mut enemies,
+            mut velocities,
+            mut reached_goal_events,
+        ) = data;
+
+        for (entity, enemy, transform, velocity, status) in (
+            &entities,
+            &mut enemies,
+            &transforms,
+            &mut velocities,
+            status_effects.maybe(),
+        ).join() {
             // Update which waypoint an enemy is heading towards
+            let waypoint = waypoints.get(enemy.current_waypoint).expect("Enemy doesn't have a waypoint. Boo.");
+            let mut nav_waypoint = navigation.waypoint(&waypoint.path, waypoint.id)
+                .expect("Waypoint isn't on its path?");
             let mut waypoint_transform = transforms.get(enemy.current_waypoint)
                 .expect("Waypoint doesn't have a transform?");
             let distance = nalgebra::distance(&transform.position, &waypoint_transform.position);
 
             if distance <= 10.0 {
-                let new_waypoint_id = waypoints.get(enemy.current_waypoint).expect("Enemy doesn't have a waypoint. Boo.")
-                    .id + 1;
-
-                let waypoint_entity = {
-                    let mut entity = None;
-                    for (ent, waypoint) in (&entities, &waypoints).join() {
-                        if waypoint.id == new_waypoint_id {
-                            entity = Some(ent);
-                        }
-                    }
-                    entity
-                }.expect(&format!("Waypoint {} does not exist", new_waypoint_id));
-                waypoint_transform = transforms.get(waypoint_entity)
-                    .expect("Really this shouldn't happen?");
-                enemy.current_waypoint = waypoint_entity;
+                if let Some(next) = navigation.next_waypoint(&waypoint.path, waypoint.id, &mut random.0) {
+                    nav_waypoint = next;
+                    waypoint_transform = transforms.get(next.entity)
+                        .expect("Really this shouldn't happen?");
+                    enemy.current_waypoint = next.entity;
+                } else {
+                    reached_goal_events.push(ReachedGoalEvent {
+                        enemy: entity,
+                        goal: enemy.current_waypoint,
+                    });
+                    // Enemies that stay to fight hold still at the goal.
+                    velocity.0 = Vector2::zeros();
+                    enemy.distance_left = 0.0;
+                    continue;
+                }
             }
 
+            // Step to whichever neighboring cell the waypoint's flow field says is closest.
+            let field = navigation.field(nav_waypoint.cell);
+            let next_cell = match (grid.cell_at(transform.position.x, transform.position.y), field) {
+                (Some(cell), Some(field)) => field.next_cell(&grid, cell),
+                _ => None,
+            };
+
+            // Head to the next cell, or straight to the waypoint without one.
+            let target = if let Some((cell_x, cell_y)) = next_cell {
+                let (x, y) = grid.cell_center(cell_x, cell_y);
+                Point2::new(x, y)
+            } else {
+                waypoint_transform.position
+            };
+
             // Now update velocity
-            let speed = velocity.0.magnitude();
-            let direction = (waypoint_transform.position - transform.position).normalize();
-            velocity.0 = speed * direction;
+            // Slows and stuns hold enemies back.
+            let speed = enemy.speed * status.map_or(1.0, StatusEffects::speed_multiplier);
+            let offset = target - transform.position;
+            if offset.magnitude() > 0.0 {
+                velocity.0 = speed * offset.normalize();
+            }
+
+            // Whatever's left of the way to the waypoint, then the rest of the route after it.
+            let steps_left = next_cell.and_then(|cell| field?.distance(cell)).unwrap_or(0)
+                + waypoints.get(nav_waypoint.entity)
+                    .and_then(|waypoint| navigation.route_distance(&waypoint.path, waypoint.id))
+                    .unwrap_or(0);
+            enemy.distance_left = offset.magnitude() + steps_left as f32 * grid.cell_size;
         }
     }
 }
@@ -263,20 +904,20 @@ impl<'a> System<'a> for DeathSystem {
         ReadStorage<'a, Base>,
         ReadStorage<'a, Enemy>,
         Read<'a, Vec<DeathEvent>>,
+        Read<'a, LevelSettings>,
         Write<'a, BuildResources>,
         Write<'a, PlayState>,
     );
 
     fn run (&mut self, data: Self::SystemData) {
-        let (bases, enemies, death_events, mut build_resources, mut play_state) = data;
+        let (bases, enemies, death_events, settings, mut build_resources, mut play_state) = data;
 
         for death in death_events.iter() {
             if let Some(_base) = bases.get(death.entity) {
                 *play_state = PlayState::Lose;
             }
-            if let Some(_enemy) = enemies.get(death.entity) {
-                // TODO: Make this tunable in data somehow.
-                build_resources.bits += 5;
+            if let Some(enemy) = enemies.get(death.entity) {
+                build_resources.bits += (enemy.bounty as f32 * settings.bounty_multiplier).round() as u32;
             }
         }
     }
@@ -289,15 +930,67 @@ impl<'a> System<'a> for WinSystem {
         ReadStorage<'a, Base>,
         ReadStorage<'a, Enemy>,
         ReadStorage<'a, Spawner>,
+        Read<'a, Waves>,
         Write<'a, PlayState>,
     );
 
     fn run (&mut self, data: Self::SystemData) {
-        let (bases, enemies, spawners, mut play_state) = data;
+        let (bases, enemies, spawners, waves, mut play_state) = data;
 
-        // Player wins When all enemies and spawners are gone and there are still bases alive.
-        if !bases.is_empty() && enemies.is_empty() && spawners.is_empty() {
+        // Player wins When all waves have been sent, all spawners are done, all enemies are gone,
+        // and there are still bases alive.
+        let spawners_idle = (&spawners).join().all(|spawner| spawner.is_idle());
+        if !bases.is_empty() && enemies.is_empty() && spawners_idle && waves.is_finished() {
             *play_state = PlayState::Win;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Point2<f32>, expected: Point2<f32>) {
+        assert!(nalgebra::distance(&actual, &expected) < 1e-3, "{} isn't close to {}", actual, expected);
+    }
+
+    #[test]
+    fn lead_stationary_targets_by_nothing() {
+        let aim = lead_target(Point2::origin(), Point2::new(100.0, 0.0), Vector2::zeros(), 50.0);
+        assert_close(aim, Point2::new(100.0, 0.0));
+    }
+
+    #[test]
+    fn lead_targets_moving_across_the_shot() {
+        // The projectile flies 125 pixels in 2.5 seconds, meeting the target 75 pixels along.
+        let aim = lead_target(Point2::origin(), Point2::new(100.0, 0.0), Vector2::new(0.0, 30.0), 50.0);
+        assert_close(aim, Point2::new(100.0, 75.0));
+    }
+
+    #[test]
+    fn lead_approaching_targets_to_the_earliest_hit() {
+        // Slower targets can only be hit once.
+        let aim = lead_target(Point2::origin(), Point2::new(100.0, 0.0), Vector2::new(-30.0, 0.0), 50.0);
+        assert_close(aim, Point2::new(62.5, 0.0));
+        // Faster targets pass the projectile's reach twice, so hit them the first time.
+        let aim = lead_target(Point2::origin(), Point2::new(100.0, 0.0), Vector2::new(-100.0, 0.0), 50.0);
+        assert_close(aim, Point2::new(100.0 / 3.0, 0.0));
+    }
+
+    #[test]
+    fn lead_targets_as_fast_as_the_projectile() {
+        let aim = lead_target(Point2::origin(), Point2::new(100.0, 0.0), Vector2::new(-50.0, 0.0), 50.0);
+        assert_close(aim, Point2::new(50.0, 0.0));
+        // Running away just as fast, it can't be caught.
+        let aim = lead_target(Point2::origin(), Point2::new(100.0, 0.0), Vector2::new(50.0, 0.0), 50.0);
+        assert_close(aim, Point2::new(100.0, 0.0));
+    }
+
+    #[test]
+    fn aim_straight_at_targets_too_fast_to_catch() {
+        let aim = lead_target(Point2::origin(), Point2::new(100.0, 0.0), Vector2::new(100.0, 0.0), 50.0);
+        assert_close(aim, Point2::new(100.0, 0.0));
+        let aim = lead_target(Point2::origin(), Point2::new(100.0, 0.0), Vector2::new(0.0, 100.0), 50.0);
+        assert_close(aim, Point2::new(100.0, 0.0));
+    }
+}
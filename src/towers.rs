@@ -0,0 +1,264 @@
+//! Rules for the player's towers, shared by the game and headless tools.
+
+use specs::prelude::*;
+
+use crate::components::*;
+use crate::data::{TowerCatalog, TowerDef};
+use crate::grid::{Grid, GridCell};
+use crate::pathfinding::{self, NavMode, Navigation};
+use crate::resources::*;
+
+/// Upgrades can't make towers attack faster than this.
+pub const MIN_SECONDS_PER_ATTACK: f32 = 0.05;
+/// Upgrades can't slow projectiles down more than this, so they still get where they're going.
+pub const MIN_PROJECTILE_SPEED: f32 = 50.0;
+
+/// Whether a tower can be built on a grid cell.
+///
+/// In mazes, towers can go on any open cell as long as enemies can still get everywhere they
+/// need to go.
+pub fn can_build_at(world: &World, cell: (u32, u32)) -> bool {
+    BuildCheck::new(world, cell).allows(world)
+}
+
+/// `can_build_at` split in two, so the pathfinding it does for mazes can be reused.
+///
+/// `new` does everything that only depends on the grid, so a check stays good until the grid
+/// changes. `allows` then only looks at where the enemies are.
+#[derive(Clone, Debug)]
+pub struct BuildCheck {
+    cell: (u32, u32),
+    /// Whether the grid alone lets a tower go on the cell.
+    grid_allows: bool,
+    /// The grid with the cell built on, in mazes.
+    blocked: Option<pathfinding::BlockedGrid>,
+}
+
+impl BuildCheck {
+    pub fn new(world: &World, cell: (u32, u32)) -> Self {
+        let grid = world.read_resource::<Grid>();
+        let navigation = world.read_resource::<Navigation>();
+        match navigation.mode {
+            NavMode::Paths => Self {
+                cell,
+                grid_allows: grid.is_buildable(cell.0, cell.1),
+                blocked: None,
+            },
+            NavMode::Maze if !grid.is_open(cell.0, cell.1) => Self {
+                cell,
+                grid_allows: false,
+                blocked: None,
+            },
+            NavMode::Maze => {
+                let legs = route_legs(world, &grid);
+                let blocked = pathfinding::BlockedGrid::new(&grid, navigation.mode, cell, &legs);
+                Self {
+                    cell,
+                    grid_allows: blocked.keeps_connected(&legs),
+                    blocked: Some(blocked),
+                }
+            }
+        }
+    }
+
+    /// The cell being checked.
+    pub fn cell(&self) -> (u32, u32) {
+        self.cell
+    }
+
+    /// Whether a tower can be built on the cell with the enemies where they are now.
+    pub fn allows(&self, world: &World) -> bool {
+        if !self.grid_allows {
+            return false;
+        }
+        let blocked = match &self.blocked {
+            Some(blocked) => blocked,
+            None => return true,
+        };
+        let grid = world.read_resource::<Grid>();
+        !enemy_cells(world, &grid).contains(&self.cell) &&
+            blocked.keeps_connected(&enemy_legs(world, &grid))
+    }
+}
+
+/// Get the grid cells enemies are currently on.
+fn enemy_cells(world: &World, grid: &Grid) -> Vec<(u32, u32)> {
+    let (transforms, enemies): (ReadStorage<Transform>, ReadStorage<Enemy>) = world.system_data();
+    (&transforms, &enemies).join()
+        .filter_map(|(transform, _)| grid.cell_at(transform.position.x, transform.position.y))
+        .collect()
+}
+
+/// Get every leg enemies will need to walk in a maze: between the waypoints on each path, and
+/// from spawners to the waypoints they send enemies to.
+fn route_legs(world: &World, grid: &Grid) -> Vec<pathfinding::Leg> {
+    let (navigation, transforms, spawners): (Read<Navigation>, ReadStorage<Transform>, ReadStorage<Spawner>) =
+        world.system_data();
+
+    let mut legs = navigation.legs();
+    for (transform, spawner) in (&transforms, &spawners).join() {
+        let start = grid.cell_at(transform.position.x, transform.position.y);
+        let goal = navigation.waypoint(&spawner.path, spawner.first_waypoint);
+        if let (Some(start), Some(goal)) = (start, goal) {
+            legs.push((start, goal.cell));
+        }
+    }
+    legs
+}
+
+/// Get the legs enemies are walking now, from where they are to the waypoints they're heading to.
+fn enemy_legs(world: &World, grid: &Grid) -> Vec<pathfinding::Leg> {
+    let (transforms, enemies): (ReadStorage<Transform>, ReadStorage<Enemy>) = world.system_data();
+    let cell_of = |transform: &Transform| grid.cell_at(transform.position.x, transform.position.y);
+
+    (&transforms, &enemies).join()
+        .filter_map(|(transform, enemy)| {
+            let goal = transforms.get(enemy.current_waypoint).and_then(cell_of)?;
+            Some((cell_of(transform)?, goal))
+        })
+        .collect()
+}
+
+/// Build a tower on a grid cell, if the level is still being played, the player can afford it
+/// and the cell can be built on. Returns the new tower.
+pub fn build_tower(world: &mut World, tower: &TowerDef, cell: (u32, u32)) -> Option<Entity> {
+    let can_build = *world.read_resource::<PlayState>() == PlayState::Play &&
+        world.read_resource::<BuildResources>().bits >= tower.cost &&
+        can_build_at(world, cell);
+    if !can_build {
+        return None;
+    }
+    let (built_on, (world_x, world_y)) = {
+        let grid = world.read_resource::<Grid>();
+        (grid.get_cell(cell.0, cell.1)?, grid.cell_center(cell.0, cell.1))
+    };
+
+    let mut builder = world.create_entity()
+        .with(Transform::new(world_x, world_y))
+        .with(tower.drawable)
+        .with(Faction::Player)
+        .with(Shooter {
+            seconds_per_attack: tower.seconds_per_attack,
+            cooldown: 0.0,
+            attack_radius: tower.attack_radius,
+            projectile_speed: tower.projectile_speed,
+            aim: tower.aim,
+            targeting: tower.targeting,
+        })
+        .with(Attacker { damage: tower.damage, damage_type: tower.damage_type })
+        .with(Tower::new(&tower.name, tower.cost, cell, built_on));
+    if let Some(area_damage) = tower.area_damage.clone() {
+        builder = builder.with(area_damage);
+    }
+    if let Some(pierce) = tower.pierce {
+        builder = builder.with(Pierce::new(pierce));
+    }
+    if let Some(beam_attack) = tower.beam.clone() {
+        builder = builder.with(beam_attack);
+    }
+    if !tower.effects.is_empty() {
+        builder = builder.with(InflictsEffects(tower.effects.clone()));
+    }
+    let entity = builder.build();
+
+    // Only take the cell and spend resources once the tower exists.
+    world.write_resource::<Grid>().set_cell(cell.0, cell.1, GridCell::Occupied);
+    world.write_resource::<BuildResources>().bits -= tower.cost;
+
+    println!("Built {} tower at {:?}!", tower.name, (world_x, world_y));
+    Some(entity)
+}
+
+/// Buy the upgrade at index `choice` of a tower's current options, if the player can afford it.
+/// Returns whether the upgrade was bought.
+pub fn upgrade_tower(world: &World, entity: Entity, choice: usize) -> bool {
+    if *world.read_resource::<PlayState>() != PlayState::Play {
+        return false;
+    }
+    let tower_catalog = world.read_resource::<TowerCatalog>();
+    let mut build_resources = world.write_resource::<BuildResources>();
+    let mut towers = world.write_storage::<Tower>();
+    let mut shooters = world.write_storage::<Shooter>();
+    let mut attackers = world.write_storage::<Attacker>();
+    let mut area_damages = world.write_storage::<AreaDamage>();
+    let mut pierces = world.write_storage::<Pierce>();
+    let mut beam_attacks = world.write_storage::<BeamAttack>();
+    let mut inflicts_effects = world.write_storage::<InflictsEffects>();
+
+    let tower = match towers.get_mut(entity) {
+        Some(tower) => tower,
+        None => return false,
+    };
+    let upgrade = tower_catalog.get(&tower.name)
+        .and_then(|tower_def| tower_def.upgrade_options(&tower.upgrades).into_iter().nth(choice));
+    let upgrade = match upgrade {
+        Some(upgrade) if upgrade.cost <= build_resources.bits => upgrade,
+        _ => return false,
+    };
+
+    // Spend resources!
+    build_resources.bits -= upgrade.cost;
+    tower.spent += upgrade.cost;
+    tower.upgrades.push(upgrade.name.clone());
+
+    if let Some(shooter) = shooters.get_mut(entity) {
+        shooter.attack_radius = (shooter.attack_radius + upgrade.attack_radius).max(0.0);
+        shooter.seconds_per_attack = (shooter.seconds_per_attack + upgrade.seconds_per_attack).max(MIN_SECONDS_PER_ATTACK);
+        shooter.projectile_speed = (shooter.projectile_speed + upgrade.projectile_speed).max(MIN_PROJECTILE_SPEED);
+        if let Some(aim) = upgrade.aim {
+            shooter.aim = aim;
+        }
+    }
+    if let Some(attacker) = attackers.get_mut(entity) {
+        attacker.damage += upgrade.damage;
+    }
+    if let Some(area_damage) = upgrade.area_damage.clone() {
+        area_damages.insert(entity, area_damage).expect("Upgraded tower doesn't exist?");
+    }
+    if let Some(pierce) = upgrade.pierce {
+        pierces.insert(entity, Pierce::new(pierce)).expect("Upgraded tower doesn't exist?");
+    }
+    if let Some(beam_attack) = upgrade.beam.clone() {
+        beam_attacks.insert(entity, beam_attack).expect("Upgraded tower doesn't exist?");
+    }
+    if !upgrade.effects.is_empty() {
+        match inflicts_effects.get_mut(entity) {
+            Some(inflicts) => inflicts.0.extend(upgrade.effects.iter().cloned()),
+            None => {
+                inflicts_effects.insert(entity, InflictsEffects(upgrade.effects.clone()))
+                    .expect("Upgraded tower doesn't exist?");
+            }
+        }
+    }
+
+    println!("Upgraded {} tower with {}!", tower.name, upgrade.name);
+    true
+}
+
+/// Sell a tower, refunding part of what was spent on it and freeing up its cell to build on.
+pub fn sell_tower(world: &World, entity: Entity) {
+    if *world.read_resource::<PlayState>() != PlayState::Play {
+        return;
+    }
+    let settings = world.read_resource::<LevelSettings>();
+    let mut build_resources = world.write_resource::<BuildResources>();
+    let mut grid = world.write_resource::<Grid>();
+    let towers = world.read_storage::<Tower>();
+
+    let tower = match towers.get(entity) {
+        Some(tower) => tower,
+        None => return,
+    };
+    if let Err(e) = world.entities().delete(entity) {
+        println!("Entity could not be deleted {}", e);
+        return;
+    }
+
+    // Put back whatever the tower was built on. Enemies' flow fields pick up the freed cell from
+    // the grid's changes.
+    grid.set_cell(tower.cell.0, tower.cell.1, tower.built_on);
+    let refund = tower.sell_value(settings.sell_refund);
+    build_resources.bits += refund;
+
+    println!("Sold {} tower for {} bits!", tower.name, refund);
+}
@@ -0,0 +1,194 @@
+use specs::prelude::*;
+
+use isengard_returns::{
+    components::*,
+    data::{EnemyCatalog, TowerCatalog},
+    grid::{Grid, GridCell},
+    level,
+    resources::*,
+    simulation::Simulation,
+    towers,
+};
+
+const DT: f32 = 1.0 / 60.0;
+const MAX_STEPS: u32 = 60 * 60 * 10;
+
+#[test]
+fn every_level_loads() {
+    let levels = level::find_levels();
+    assert!(!levels.is_empty());
+    for level_name in levels {
+        let mut simulation = Simulation::new();
+        if let Err(e) = simulation.load_level(&level_name) {
+            panic!("Could not load level \"{}\": {}", level_name, e);
+        }
+    }
+}
+
+#[test]
+fn undefended_level_is_lost() {
+    let mut simulation = Simulation::new();
+    simulation.load_level("test").unwrap();
+    assert_eq!(simulation.run(DT, MAX_STEPS), PlayState::Lose);
+
+    // The base was destroyed.
+    let bases = simulation.world.read_storage::<Base>();
+    assert_eq!((&bases).join().count(), 0);
+}
+
+#[test]
+fn runs_are_deterministic() {
+    let run = || {
+        let mut simulation = Simulation::new();
+        simulation.load_level("lanes").unwrap();
+        let mut steps = 0;
+        while simulation.play_state() == PlayState::Play && steps < MAX_STEPS {
+            simulation.step(DT);
+            steps += 1;
+        }
+        let bits = simulation.world.read_resource::<BuildResources>().bits;
+        (simulation.play_state(), steps, bits)
+    };
+    assert_eq!(run(), run());
+}
+
+/// Get the HP the level's base has left.
+fn base_hp(simulation: &Simulation) -> u32 {
+    let (bases, healths) = (simulation.world.read_storage::<Base>(), simulation.world.read_storage::<Health>());
+    (&bases, &healths).join().map(|(_, health)| health.current_hp).next().unwrap_or(0)
+}
+
+#[test]
+fn melee_enemies_survive_their_first_hit() {
+    let mut simulation = Simulation::new();
+    simulation.load_level("lanes").unwrap();
+    for enemy in simulation.world.write_resource::<EnemyCatalog>().enemies.iter_mut() {
+        enemy.melee = true;
+    }
+
+    let start_hp = base_hp(&simulation);
+    let mut steps = 0;
+    while base_hp(&simulation) == start_hp && steps < MAX_STEPS {
+        simulation.step(DT);
+        steps += 1;
+    }
+    assert!(base_hp(&simulation) < start_hp);
+
+    // The enemy that hit the base is still there, and keeps hitting it.
+    let attacker = {
+        let (entities, enemies) = (simulation.world.entities(), simulation.world.read_storage::<Enemy>());
+        (&entities, &enemies).join().find(|(_, enemy)| enemy.distance_left == 0.0).map(|(entity, _)| entity)
+    };
+    let attacker = attacker.expect("Melee enemy was used up by its first hit");
+    let hp = base_hp(&simulation);
+    for _ in 0..70 {
+        simulation.step(DT);
+    }
+    assert!(simulation.world.is_alive(attacker));
+    assert!(base_hp(&simulation) < hp);
+}
+
+/// Find the first buildable cell that a tower can be built on.
+fn buildable_cell(simulation: &Simulation) -> (u32, u32) {
+    let grid = simulation.world.read_resource::<Grid>().clone();
+    (0..grid.height)
+        .flat_map(|y| (0..grid.width).map(move |x| (x, y)))
+        .find(|&(x, y)| grid.is_buildable(x, y) && towers::can_build_at(&simulation.world, (x, y)))
+        .expect("No cell to build on")
+}
+
+#[test]
+fn towers_can_be_built_upgraded_and_sold() {
+    let mut simulation = Simulation::new();
+    simulation.load_level("test2").unwrap();
+    simulation.world.write_resource::<BuildResources>().bits = 100;
+    let arrow = simulation.world.read_resource::<TowerCatalog>().get("arrow").cloned().unwrap();
+    let cell = buildable_cell(&simulation);
+
+    let tower = towers::build_tower(&mut simulation.world, &arrow, cell).unwrap();
+    assert_eq!(simulation.world.read_resource::<Grid>().get_cell(cell.0, cell.1), Some(GridCell::Occupied));
+    assert!(towers::build_tower(&mut simulation.world, &arrow, cell).is_none());
+
+    // Only the first tier can be bought at first.
+    let damage = simulation.world.read_storage::<Attacker>().get(tower).unwrap().damage;
+    assert!(!towers::upgrade_tower(&simulation.world, tower, 1));
+    assert!(towers::upgrade_tower(&simulation.world, tower, 0));
+    assert_eq!(simulation.world.read_storage::<Attacker>().get(tower).unwrap().damage, damage + arrow.upgrades[0].damage);
+
+    let spent = arrow.cost + arrow.upgrades[0].cost;
+    let bits = simulation.world.read_resource::<BuildResources>().bits;
+    assert_eq!(bits, 100 - spent);
+
+    towers::sell_tower(&simulation.world, tower);
+    simulation.step(DT);
+    assert!(!simulation.world.is_alive(tower));
+    let refund = (spent as f32 * simulation.world.read_resource::<LevelSettings>().sell_refund).round() as u32;
+    assert_eq!(simulation.world.read_resource::<BuildResources>().bits, bits + refund);
+    assert_eq!(simulation.world.read_resource::<Grid>().get_cell(cell.0, cell.1), Some(GridCell::Buildable));
+}
+
+#[test]
+fn upgrades_cant_stop_projectiles() {
+    let mut simulation = Simulation::new();
+    simulation.load_level("test2").unwrap();
+    simulation.world.write_resource::<BuildResources>().bits = 100;
+    let arrow = {
+        let mut catalog = simulation.world.write_resource::<TowerCatalog>();
+        let arrow = catalog.towers.iter_mut().find(|tower| tower.name == "arrow").unwrap();
+        arrow.upgrades[0].projectile_speed = -arrow.projectile_speed * 2.0;
+        arrow.upgrades[0].attack_radius = -arrow.attack_radius * 2.0;
+        arrow.clone()
+    };
+    let cell = buildable_cell(&simulation);
+
+    let tower = towers::build_tower(&mut simulation.world, &arrow, cell).unwrap();
+    assert!(towers::upgrade_tower(&simulation.world, tower, 0));
+    let shooters = simulation.world.read_storage::<Shooter>();
+    let shooter = shooters.get(tower).unwrap();
+    assert_eq!(shooter.projectile_speed, towers::MIN_PROJECTILE_SPEED);
+    assert_eq!(shooter.attack_radius, 0.0);
+}
+
+#[test]
+fn selling_puts_back_the_cell_built_on() {
+    let mut simulation = Simulation::new();
+    simulation.load_level("maze").unwrap();
+    simulation.world.write_resource::<BuildResources>().bits = 100;
+    let arrow = simulation.world.read_resource::<TowerCatalog>().get("arrow").cloned().unwrap();
+    // Maze towers can go on painted path too.
+    let cell = buildable_cell(&simulation);
+    simulation.world.write_resource::<Grid>().set_cell(cell.0, cell.1, GridCell::Walkable);
+    assert!(towers::can_build_at(&simulation.world, cell));
+
+    let tower = towers::build_tower(&mut simulation.world, &arrow, cell).unwrap();
+    towers::sell_tower(&simulation.world, tower);
+    assert_eq!(simulation.world.read_resource::<Grid>().get_cell(cell.0, cell.1), Some(GridCell::Walkable));
+}
+
+#[test]
+fn build_checks_keep_up_with_enemies() {
+    let mut simulation = Simulation::new();
+    simulation.load_level("maze").unwrap();
+    let (width, height) = {
+        let grid = simulation.world.read_resource::<Grid>();
+        (grid.width, grid.height)
+    };
+    let checks: Vec<_> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|cell| towers::BuildCheck::new(&simulation.world, cell))
+        .collect();
+
+    // Nothing's built, so the checks stay good while the enemies walk around.
+    for step in 0..MAX_STEPS {
+        simulation.step(DT);
+        if simulation.play_state() != PlayState::Play {
+            break;
+        }
+        if step % 60 != 0 {
+            continue;
+        }
+        for check in &checks {
+            assert_eq!(check.allows(&simulation.world), towers::can_build_at(&simulation.world, check.cell()));
+        }
+    }
+}